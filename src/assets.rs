@@ -43,6 +43,10 @@ pub struct Assets {
   pub arrow_width: f32,
   pub up_arrow: graphics::Mesh,
   pub down_arrow: graphics::Mesh,
+  pub left_arrow: graphics::Mesh,
+  pub right_arrow: graphics::Mesh,
+  // One pixel long; scale it out to the length of a hold note
+  pub hold_bar: graphics::Mesh,
 }
 
 impl Assets {
@@ -186,6 +190,37 @@ impl Assets {
       graphics::Color::from_rgb(0, 32, 192)
     ).unwrap();
 
+    // Sideways arrows are only used in four-lane mode, for steps rather than leaps
+    let left_arrow = graphics::Mesh::new_polygon(
+      ctx,
+      graphics::DrawMode::fill(),
+      &[
+        Point2::new(-arrow_width/2.0, 0.0),
+        Point2::new(arrow_width/2.0, -arrow_height/2.0),
+        Point2::new(arrow_width/2.0, arrow_height/2.0),
+      ],
+      graphics::Color::from_rgb(192, 0, 96)
+    ).unwrap();
+
+    let right_arrow = graphics::Mesh::new_polygon(
+      ctx,
+      graphics::DrawMode::fill(),
+      &[
+        Point2::new(arrow_width/2.0, 0.0),
+        Point2::new(-arrow_width/2.0, arrow_height/2.0),
+        Point2::new(-arrow_width/2.0, -arrow_height/2.0),
+      ],
+      graphics::Color::from_rgb(224, 144, 0)
+    ).unwrap();
+
+    let hold_bar_height = 8.0;
+    let hold_bar = graphics::Mesh::new_rectangle(
+      ctx,
+      graphics::DrawMode::fill(),
+      graphics::Rect::new(0.0, -hold_bar_height/2.0, 1.0, hold_bar_height),
+      graphics::Color::from_rgb(255, 255, 255)
+    ).unwrap();
+
     Assets {
       font: font,
 
@@ -225,6 +260,9 @@ impl Assets {
       arrow_width: arrow_width,
       up_arrow: up_arrow,
       down_arrow: down_arrow,
+      left_arrow: left_arrow,
+      right_arrow: right_arrow,
+      hold_bar: hold_bar,
     }
   }
 }
@@ -0,0 +1,1087 @@
+use std::{
+  collections::{HashMap, HashSet},
+  io::Cursor,
+  mem,
+  rc::Rc,
+  time::Duration,
+};
+
+use ggez::{event::MouseButton, graphics, timer, input::keyboard::{KeyCode, KeyMods}, Context, GameError, GameResult};
+use rodio::Sink;
+use midly::Smf;
+use nalgebra::{Point2, Vector2};
+
+use crate::anim;
+use crate::assets::Assets;
+use crate::chart::{LaneMode, NavDirection, PatternNote, RelativePitch, get_pattern};
+use crate::chart_cues::{ChartCue, get_chart_cues};
+use crate::difficulty::Difficulty;
+use crate::encounter::{ActionSource, ActionTarget, AttackModifier, CombatAction, CombatEffects, Encounter, EnemyState, HeroState};
+use crate::enemy_ai::EnemyAi;
+use crate::judgement::{Grade, Judgement, NoteJudge};
+use crate::results::{BattleOutcome, BattleSummary, ResultsScene};
+use crate::scene::{Scene, SceneTransition, World};
+use crate::score::ScoreState;
+use crate::song::SongManifest;
+use crate::song_timeline::{SongClock, song_timeline};
+use crate::status::{StatusDuration, StatusEffects};
+use crate::timing::{MidiTiming, get_timing};
+
+// How long a grade stays on screen after a judgement
+const JUDGEMENT_DISPLAY_MS: i32 = 500;
+
+// How long the victory or defeat sequence plays, while the music fades out, before the results
+const ENDING_MS: i32 = 3000;
+
+const FALLEN_TINT: graphics::Color = graphics::Color { r: 1.0, g: 1.0, b: 1.0, a: 0.3 };
+
+// Share of an enemy action that perfectly hitting its measure blocks
+const MAX_ENEMY_ACTION_MITIGATION: f64 = 0.75;
+
+// Share of max HP that a perfectly played rest recovers
+const REST_HEAL_FRACTION: f64 = 0.25;
+
+// Share of damage that a perfectly played defend blocks
+const DEFEND_DAMAGE_REDUCTION: f64 = 0.5;
+
+// Share of attack power that each strike of a multi-hit does
+const MULTI_HIT_POWER: f64 = 0.4;
+
+// Share of attack power that the extra strike from haste does
+const HASTE_STRIKE_POWER: f64 = 0.5;
+
+// How long attack and effect visuals stay up after an action resolves
+const ACTION_EFFECT_MS: i32 = 400;
+
+struct DirectionInput {
+  direction: NavDirection,
+  time: i32,
+}
+
+struct BattleEnding {
+  outcome: BattleOutcome,
+  start_time: i32,
+}
+
+struct BgAnim {
+  animation: anim::Animation,
+  position: Point2<f32>,
+  scale: Vector2<f32>,
+  distance: f32
+}
+
+pub struct BattleScene {
+  song: SongManifest,
+  assets: Rc<Assets>,
+  bg_anims: Vec<BgAnim>,
+  dt: Duration,
+  clock: SongClock,
+  // Keys pressed since the last update, in the order they were pressed
+  direction_inputs: Vec<DirectionInput>,
+  // Keys let go since the last update, with the song time they were let go
+  releases: Vec<(NavDirection, i32)>,
+  // Hold notes currently being held down, by the key holding them
+  held_notes: HashMap<NavDirection, usize>,
+  timing: MidiTiming,
+  pattern: Vec<PatternNote>,
+  note_judge: NoteJudge,
+  last_judgement: Option<Judgement>,
+  score: ScoreState,
+  sink: Sink,
+  heroes: Vec<HeroState>,
+  enemies: Vec<EnemyState>,
+  actions: HashMap<usize, Vec<CombatAction>>,
+  // One per enemy, for those that pick their own actions
+  enemy_ais: Vec<Option<EnemyAi>>,
+  input_measures: HashSet<usize>,
+  command_window_hero: usize,
+  // Last input measure in which a command was chosen
+  last_command_measure: Option<usize>,
+  // Last measure whose notes are all judged, and whose dependent actions have resolved
+  last_measure_judged: Option<usize>,
+  last_beat_ticked: i64,
+  damage_dealt: u32,
+  damage_taken: u32,
+  ending: Option<BattleEnding>,
+  camera_offset: Vector2<f32>,
+  camera_zoom: f32,
+  input_offset_ms: i32,
+}
+
+impl BattleScene {
+  pub fn new(ctx: &mut Context, world: &World, song_path: &str, difficulty: Difficulty) -> GameResult<BattleScene> {
+    let song = SongManifest::load(ctx, song_path)?;
+    let mut encounter = Encounter::load(ctx, &song.encounter)?;
+
+    let midi_bytes = song.read_chart(ctx)?;
+    let midi = Smf::parse(&midi_bytes)
+      .map_err(|e| GameError::ResourceLoadError(format!("Invalid chart {}: {}", song.chart, e)))?;
+    let timing = get_timing(&midi, song.bpm, song.audio_offset_ms);
+    // Song select already showed the quantization report
+    let (pattern, _) = get_pattern(&midi, &timing, &song.sources, &song.chords, song.quantize.as_ref(), difficulty);
+
+    // Cues written into the chart add to whatever the encounter file schedules
+    let cue_error = |e: String| GameError::ResourceLoadError(format!("Invalid cue in chart {}: {}", song.chart, e));
+    for (measure_idx, cue) in get_chart_cues(&midi, &timing, song.control_track).map_err(cue_error)? {
+      match cue {
+        ChartCue::Action(action) => encounter.add_action(measure_idx, action).map_err(cue_error)?,
+        ChartCue::HeroTurn => { encounter.input_measures.insert(measure_idx); },
+      }
+    }
+    let note_judge = NoteJudge::new(world.settings.judgement_windows.clone(), song.lanes, &pattern);
+
+    let device = rodio::default_output_device()
+      .ok_or_else(|| GameError::AudioError("No audio output device".to_string()))?;
+    let sink = Sink::new(&device);
+    // sink.set_volume(0.0);
+    sink.pause();
+
+    let audio_bytes = song.read_audio(ctx)?;
+    let music_source = rodio::Decoder::new(Cursor::new(audio_bytes))
+      .map_err(|e| GameError::AudioError(format!("Can't decode {}: {}", song.audio, e)))?;
+    let (song_source, clock) = song_timeline(music_source, song.lead_in_ms);
+    sink.append(song_source);
+
+    let assets = world.assets.clone();
+
+    let bg_anims = vec!(
+      BgAnim {
+        animation: anim::Animation::new(assets.sky_anim.clone()),
+        position: Point2::<f32>::new(0.0, 0.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 3.0,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.grass_anim.clone()),
+        position: Point2::<f32>::new(0.0, 5.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 2.0,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.left_tree_anim.clone()),
+        position: Point2::<f32>::new(0.0, 0.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 1.5,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.right_tree_anim.clone()),
+        position: Point2::<f32>::new(574.0, 0.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 1.5,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.rocks_anim.clone()),
+        position: Point2::<f32>::new(79.0, 342.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 1.0,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.dirt_anim.clone()),
+        position: Point2::<f32>::new(0.0, 355.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 0.7,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.left_bush_anim.clone()),
+        position: Point2::<f32>::new(0.0, 374.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 0.5,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.right_bush_anim.clone()),
+        position: Point2::<f32>::new(805.0, 460.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 0.5,
+      },
+      BgAnim {
+        animation: anim::Animation::new(assets.wind_anim.clone()),
+        position: Point2::<f32>::new(0.0, 360.0),
+        scale: Vector2::<f32>::new(1.0, 1.0),
+        distance: 0.5,
+      },
+    );
+
+    Ok(BattleScene {
+      song: song,
+      assets: assets,
+      bg_anims: bg_anims,
+      dt: Duration::default(),
+      clock: clock,
+      direction_inputs: Vec::new(),
+      releases: Vec::new(),
+      held_notes: HashMap::new(),
+      timing: timing,
+      pattern: pattern,
+      note_judge: note_judge,
+      last_judgement: None,
+      score: ScoreState::new(),
+      sink: sink,
+      heroes: encounter.heroes,
+      enemies: encounter.enemies,
+      actions: encounter.actions,
+      enemy_ais: encounter.enemy_behaviors
+        .into_iter()
+        .enumerate()
+        .map(|(idx, behavior)| behavior.map(|b| EnemyAi::new(idx, b)))
+        .collect(),
+      input_measures: encounter.input_measures,
+      command_window_hero: 0,
+      last_command_measure: None,
+      last_measure_judged: None,
+      last_beat_ticked: 0,
+      damage_dealt: 0,
+      damage_taken: 0,
+      ending: None,
+      camera_offset: Vector2::new(0.0, 0.0),
+      camera_zoom: 1.0,
+      input_offset_ms: world.settings.input_offset_ms,
+    })
+  }
+
+  fn draw_command_window(&self, ctx: &mut Context, hero: &HeroState) {
+    let center_point = self.camera_point(1.0, Point2::new(hero.position.x + 60.0, hero.position.y + 70.0));
+
+    graphics::draw(
+      ctx,
+      &self.assets.cursor,
+      graphics::DrawParam::default().dest(center_point)
+    ).unwrap();
+
+    graphics::draw(
+      ctx,
+      &self.assets.button,
+      graphics::DrawParam::default().dest(center_point + Vector2::new(self.assets.button_width + self.assets.button_margin, 0.0))
+    ).unwrap();
+
+    graphics::draw(
+      ctx,
+      &graphics::Text::new(("Stk", self.assets.font, 40.0)),
+      graphics::DrawParam::default().dest(center_point + Vector2::new(self.assets.button_width + self.assets.button_margin + 10.0, 10.0))
+    ).unwrap();
+
+    graphics::draw(
+      ctx,
+      &self.assets.button,
+      graphics::DrawParam::default().dest(center_point - Vector2::new(self.assets.button_width + self.assets.button_margin, 0.0))
+    ).unwrap();
+
+    graphics::draw(
+      ctx,
+      &graphics::Text::new(("Rst", self.assets.font, 40.0)),
+      graphics::DrawParam::default().dest(center_point + Vector2::new(-self.assets.button_width, 10.0))
+    ).unwrap();
+
+  }
+
+  // How well the notes in a measure were hit, from 0 to 1; measures without notes count as perfect
+  fn measure_accuracy(&self, measure_idx: usize) -> f64 {
+    self.note_judge
+      .accuracy_between(&self.pattern, self.timing.measure_start_ms(measure_idx), self.timing.measure_start_ms(measure_idx + 1))
+      .unwrap_or(1.0)
+  }
+
+  // The measure whose command window is open at the given time, if any
+  fn open_command_measure(&self, time: i32) -> Option<usize> {
+    let measure_idx = self.timing.measure_idx_at_ms(time as f64);
+    let hero = &self.heroes[self.command_window_hero];
+    let hero_can_act = hero.hp > 0 && !hero.effects.statuses.is_stunned();
+    if hero_can_act && self.input_measures.contains(&measure_idx) && self.last_command_measure != Some(measure_idx) {
+      Some(measure_idx)
+    } else {
+      None
+    }
+  }
+
+  // Schedules the hero's chosen action for the measure after the input measure
+  fn choose_command(&mut self, measure_idx: usize, direction: NavDirection) {
+    let src = ActionSource::Hero { idx: self.command_window_hero };
+    let action = match direction {
+      NavDirection::Right => match self.enemies.iter().position(|enemy| enemy.hp > 0) {
+        Some(idx) => CombatAction::Attack { src: src, tgt: ActionTarget::Enemy { idx: idx } },
+        None => return,
+      },
+      NavDirection::Left => CombatAction::Rest { src: src },
+      NavDirection::Up | NavDirection::Down => return,
+    };
+    self.actions.entry(measure_idx + 1).or_default().push(action);
+    self.last_command_measure = Some(measure_idx);
+
+    // The next input measure goes to the next hero in the party
+    if let Some(next_hero) = self.next_living_hero(self.command_window_hero) {
+      self.command_window_hero = next_hero;
+    }
+  }
+
+  // The first living hero after the given one, wrapping around the party
+  fn next_living_hero(&self, after_idx: usize) -> Option<usize> {
+    (1..=self.heroes.len())
+      .map(|n| (after_idx + n) % self.heroes.len())
+      .find(|&idx| self.heroes[idx].hp > 0)
+  }
+
+  // Hostile actions aimed at someone who has already fallen go to the first of their side still
+  // standing instead; None if that whole side is down
+  fn living_target(&self, action: CombatAction) -> Option<CombatAction> {
+    let tgt = match action.tgt() {
+      Some(tgt) => tgt,
+      None => return Some(action),
+    };
+    let new_tgt = match (action.src(), tgt) {
+      (ActionSource::Enemy{ .. }, ActionTarget::Hero{ idx }) if self.heroes[idx].hp == 0 => {
+        ActionTarget::Hero { idx: self.heroes.iter().position(|hero| hero.hp > 0)? }
+      },
+      (ActionSource::Hero{ .. }, ActionTarget::Enemy{ idx }) if self.enemies[idx].hp == 0 => {
+        ActionTarget::Enemy { idx: self.enemies.iter().position(|enemy| enemy.hp > 0)? }
+      },
+      _ => tgt,
+    };
+    Some(action.with_tgt(new_tgt))
+  }
+
+  fn effects(&self, who: ActionTarget) -> &CombatEffects {
+    match who {
+      ActionTarget::Hero{ idx } => &self.heroes[idx].effects,
+      ActionTarget::Enemy{ idx } => &self.enemies[idx].effects,
+    }
+  }
+
+  fn effects_mut(&mut self, who: ActionTarget) -> &mut CombatEffects {
+    match who {
+      ActionTarget::Hero{ idx } => &mut self.heroes[idx].effects,
+      ActionTarget::Enemy{ idx } => &mut self.enemies[idx].effects,
+    }
+  }
+
+  // Fallen heroes and enemies can't be healed back up
+  fn heal(&mut self, who: ActionTarget, amount: u32) {
+    let (hp, max_hp) = match who {
+      ActionTarget::Hero{ idx } => { let hero = &mut self.heroes[idx]; (&mut hero.hp, hero.max_hp) },
+      ActionTarget::Enemy{ idx } => { let enemy = &mut self.enemies[idx]; (&mut enemy.hp, enemy.max_hp) },
+    };
+    if *hp > 0 {
+      *hp = (*hp + amount).min(max_hp);
+    }
+  }
+
+  // Effect multiplier scales the action's strength, from 0 to 1
+  fn resolve_action(&mut self, action: CombatAction, measure_idx: usize, effect_multiplier: f64) {
+    let src_hp = match action.src() {
+      ActionSource::Hero{ idx } => self.heroes[idx].hp,
+      ActionSource::Enemy{ idx } => self.enemies[idx].hp,
+    };
+    let src_statuses = &self.effects(action.src().into()).statuses;
+    if src_hp == 0 || src_statuses.is_stunned() {
+      return;
+    }
+    let hasted = src_statuses.is_hasted();
+    let action = match self.living_target(action) {
+      Some(action) => action,
+      None => return,
+    };
+
+    match action {
+      CombatAction::Attack { src, tgt } => {
+        self.resolve_attack(src, tgt, measure_idx, effect_multiplier);
+        if hasted {
+          self.resolve_attack(src, tgt, measure_idx, HASTE_STRIKE_POWER * effect_multiplier);
+        }
+      },
+      CombatAction::Rest { src } => {
+        let max_hp = match src {
+          ActionSource::Hero{ idx } => self.heroes[idx].max_hp,
+          ActionSource::Enemy{ idx } => self.enemies[idx].max_hp,
+        };
+        self.heal(src.into(), ((max_hp as f64) * REST_HEAL_FRACTION * effect_multiplier).round() as u32);
+      },
+      CombatAction::Heal { tgt, amount, .. } => {
+        self.heal(tgt, ((amount as f64) * effect_multiplier).round() as u32);
+      },
+      CombatAction::Defend { src } => {
+        self.effects_mut(src.into()).guard = Some((DEFEND_DAMAGE_REDUCTION * effect_multiplier, measure_idx + 1));
+      },
+      CombatAction::Buff { tgt, multiplier, measures, .. } | CombatAction::Debuff { tgt, multiplier, measures, .. } => {
+        self.effects_mut(tgt).attack_modifiers.push(AttackModifier {
+          multiplier: 1.0 + (multiplier - 1.0) * effect_multiplier,
+          last_measure: measure_idx + measures,
+        });
+      },
+      CombatAction::MultiHit { src, tgt, hits } => {
+        let hits = if hasted { hits + 1 } else { hits };
+        for _ in 0..hits {
+          self.resolve_attack(src, tgt, measure_idx, MULTI_HIT_POWER * effect_multiplier);
+        }
+      },
+      CombatAction::Inflict { tgt, status, duration, .. } => {
+        // A poor performance can only shorten a status, not stop it outright
+        let scale = |n: u32| (((n as f64) * effect_multiplier).round() as u32).max(1);
+        let duration = match duration {
+          StatusDuration::Beats(n) => StatusDuration::Beats(scale(n)),
+          StatusDuration::Measures(n) => StatusDuration::Measures(scale(n)),
+        };
+        let target_alive = match tgt {
+          ActionTarget::Hero{ idx } => self.heroes[idx].hp > 0,
+          ActionTarget::Enemy{ idx } => self.enemies[idx].hp > 0,
+        };
+        if target_alive {
+          self.effects_mut(tgt).statuses.add(status, duration);
+        }
+      },
+    }
+  }
+
+  fn resolve_attack(&mut self, src: ActionSource, tgt: ActionTarget, measure_idx: usize, damage_multiplier: f64) {
+    let attack_power = match src {
+      ActionSource::Hero{ idx } => self.heroes[idx].attack_power,
+      ActionSource::Enemy{ idx } => self.enemies[idx].attack_power
+    };
+    let attack_multiplier = self.effects(src.into()).attack_multiplier(measure_idx);
+    let guard_multiplier = 1.0 - self.effects(tgt).guard_reduction(measure_idx);
+    let damage = ((attack_power as f64) * attack_multiplier * damage_multiplier * guard_multiplier).round() as u32;
+    self.apply_damage(tgt, damage);
+
+    if let (ActionSource::Hero{ idx: hero_idx }, ActionTarget::Enemy{ idx: enemy_idx }) = (src, tgt) {
+      if let Some(ai) = &mut self.enemy_ais[enemy_idx] {
+        ai.last_attacker = Some(hero_idx);
+      }
+    }
+  }
+
+  fn apply_damage(&mut self, tgt: ActionTarget, damage: u32) {
+    let damage = self.effects_mut(tgt).statuses.absorb_damage(damage);
+    let hp = match tgt {
+      ActionTarget::Hero{ idx } => {
+        let dealt = damage.min(self.heroes[idx].hp);
+        self.heroes[idx].hp -= dealt;
+        self.damage_taken += dealt;
+        self.heroes[idx].hp
+      },
+      ActionTarget::Enemy{ idx } => {
+        let dealt = damage.min(self.enemies[idx].hp);
+        self.enemies[idx].hp -= dealt;
+        self.damage_dealt += dealt;
+        self.enemies[idx].hp
+      },
+    };
+    if hp == 0 {
+      self.effects_mut(tgt).statuses.clear();
+    }
+  }
+
+  // Status effects act and count down on every beat of the song
+  fn tick_statuses(&mut self, beat_idx: i64) {
+    let measure_started = beat_idx > 0 && {
+      let measure_map = &self.timing.measure_map;
+      measure_map.measure_idx_at_beat(beat_idx as f64) != measure_map.measure_idx_at_beat((beat_idx - 1) as f64)
+    };
+
+    let combatants: Vec<ActionTarget> = (0..self.heroes.len())
+      .map(|idx| ActionTarget::Hero { idx: idx })
+      .chain((0..self.enemies.len()).map(|idx| ActionTarget::Enemy { idx: idx }))
+      .collect();
+    for who in combatants {
+      let poison_damage = self.effects(who).statuses.poison_damage();
+      if poison_damage > 0 {
+        self.apply_damage(who, poison_damage);
+      }
+      self.effects_mut(who).statuses.tick_beat(measure_started);
+    }
+  }
+
+  fn decided_outcome(&self) -> Option<BattleOutcome> {
+    if self.enemies.iter().all(|enemy| enemy.hp == 0) {
+      Some(BattleOutcome::Victory)
+    } else if self.heroes.iter().all(|hero| hero.hp == 0) {
+      Some(BattleOutcome::Defeat)
+    } else {
+      None
+    }
+  }
+
+  fn results(&self, outcome: BattleOutcome) -> SceneTransition {
+    let summary = BattleSummary {
+      outcome: outcome,
+      damage_dealt: self.damage_dealt,
+      damage_taken: self.damage_taken,
+      score: self.score.clone(),
+    };
+    SceneTransition::Replace(Box::new(ResultsScene::new(&self.song, summary)))
+  }
+
+  // Resolves the actions that depend on how a measure was played, once all its notes are judged:
+  // hero actions in the following measure, and enemy actions in the measure itself
+  fn resolve_judged_measure(&mut self, measure_idx: usize) {
+    let accuracy = self.measure_accuracy(measure_idx);
+
+    for action in self.actions.get(&measure_idx).cloned().unwrap_or_default() {
+      if let ActionSource::Enemy{ .. } = action.src() {
+        self.resolve_action(action, measure_idx, 1.0 - accuracy * MAX_ENEMY_ACTION_MITIGATION);
+      }
+    }
+
+    for action in self.actions.get(&(measure_idx + 1)).cloned().unwrap_or_default() {
+      if let ActionSource::Hero{ .. } = action.src() {
+        self.resolve_action(action, measure_idx + 1, accuracy);
+      }
+    }
+  }
+
+  fn source_point(&self, src: ActionSource) -> Point2<f32> {
+    match src {
+      ActionSource::Hero{ idx } => self.heroes[idx].position + Vector2::new(200.0, 180.0),
+      ActionSource::Enemy{ idx } => self.enemies[idx].position + Vector2::new(220.0, 165.0),
+    }
+  }
+
+  fn target_point(&self, tgt: ActionTarget) -> Point2<f32> {
+    match tgt {
+      ActionTarget::Hero{ idx } => self.heroes[idx].position + Vector2::new(90.0, 180.0),
+      ActionTarget::Enemy{ idx } => self.enemies[idx].position + Vector2::new(180.0, 145.0),
+    }
+  }
+
+  fn action_color(action: &CombatAction) -> graphics::Color {
+    match action {
+      CombatAction::Attack { src: ActionSource::Hero { .. }, .. } => graphics::Color::from_rgba(0, 0, 255, 192),
+      CombatAction::Attack { src: ActionSource::Enemy { .. }, .. } => graphics::Color::from_rgba(255, 0, 0, 128),
+      CombatAction::MultiHit { src: ActionSource::Hero { .. }, .. } => graphics::Color::from_rgba(0, 160, 255, 192),
+      CombatAction::MultiHit { src: ActionSource::Enemy { .. }, .. } => graphics::Color::from_rgba(255, 110, 0, 160),
+      CombatAction::Rest { .. } => graphics::Color::from_rgba(0, 192, 0, 192),
+      CombatAction::Heal { .. } => graphics::Color::from_rgba(80, 255, 120, 192),
+      CombatAction::Defend { .. } => graphics::Color::from_rgba(140, 140, 140, 192),
+      CombatAction::Buff { .. } => graphics::Color::from_rgba(255, 200, 0, 192),
+      CombatAction::Debuff { .. } => graphics::Color::from_rgba(150, 0, 200, 192),
+      CombatAction::Inflict { status, .. } => graphics::Color { a: 0.75, ..status.icon_color() },
+    }
+  }
+
+  fn draw_effect(&self, ctx: &mut Context, point: Point2<f32>, color: graphics::Color) {
+    graphics::draw(
+      ctx,
+      &self.assets.after_attack_effect,
+      graphics::DrawParam::default()
+        .dest(self.camera_point(1.0, point))
+        .scale(self.camera_scale(1.0, Vector2::new(1.0, 1.0)))
+        .color(color)
+    ).unwrap();
+  }
+
+  // Lines from source to target while the action winds up, then an effect where it lands
+  fn draw_action(&self, ctx: &mut Context, action: &CombatAction, telegraph_start_time: i32, action_time: i32, time: i32) {
+    let color = BattleScene::action_color(action);
+    let landing_point = match action.tgt() {
+      Some(tgt) => self.target_point(tgt),
+      None => self.target_point(action.src().into()),
+    };
+
+    if time > telegraph_start_time && time < action_time {
+      if action.tgt().is_some() {
+        let width = match action {
+          CombatAction::Attack { .. } | CombatAction::MultiHit { .. } => 20.0,
+          _ => 10.0,
+        };
+        let line = graphics::Mesh::new_line(
+          ctx,
+          &[self.camera_point(1.0, self.source_point(action.src())), self.camera_point(1.0, landing_point)],
+          width,
+          color
+        ).unwrap();
+        graphics::draw(
+          ctx,
+          &line,
+          graphics::DrawParam::default()
+            .scale(self.camera_scale(1.0, Vector2::new(1.0, 1.0)))
+        ).unwrap();
+      }
+    } else if time > action_time && time < action_time + ACTION_EFFECT_MS {
+      match *action {
+        CombatAction::MultiHit { hits, .. } => {
+          // One flash per strike, each a little further along
+          let hit_idx = (time - action_time) * (hits as i32)/ACTION_EFFECT_MS;
+          self.draw_effect(ctx, landing_point + Vector2::new(15.0, -10.0) * (hit_idx as f32), color);
+        },
+        _ => self.draw_effect(ctx, landing_point, color),
+      }
+    }
+  }
+
+  // The arrow for a note on the music bar, and its color for drawing hold bars
+  fn note_arrow(&self, note: &PatternNote) -> (&graphics::Mesh, graphics::Color) {
+    let direction = match self.song.lanes {
+      LaneMode::TwoLane => match note.relative_pitch {
+        RelativePitch::High => NavDirection::Up,
+        RelativePitch::Low => NavDirection::Down,
+      },
+      LaneMode::FourLane => note.lane,
+    };
+    match direction {
+      NavDirection::Up => (&self.assets.up_arrow, graphics::Color::from_rgb(0, 192, 32)),
+      NavDirection::Right => (&self.assets.right_arrow, graphics::Color::from_rgb(224, 144, 0)),
+      NavDirection::Down => (&self.assets.down_arrow, graphics::Color::from_rgb(0, 32, 192)),
+      NavDirection::Left => (&self.assets.left_arrow, graphics::Color::from_rgb(192, 0, 96)),
+    }
+  }
+
+  // A row of labelled badges, one per active status effect
+  fn draw_status_icons(&self, ctx: &mut Context, statuses: &StatusEffects, start: Point2<f32>) {
+    for (i, effect) in statuses.iter().enumerate() {
+      let center = start + Vector2::new(20.0 + 44.0 * (i as f32), 18.0);
+      let badge = graphics::Mesh::new_circle(
+        ctx,
+        graphics::DrawMode::fill(),
+        center,
+        20.0,
+        0.5,
+        effect.kind.icon_color()
+      ).unwrap();
+      graphics::draw(ctx, &badge, graphics::DrawParam::default()).unwrap();
+
+      let label = graphics::Text::new((effect.kind.icon_label(), self.assets.font, 16.0));
+      let label_size = Vector2::new(label.width(ctx) as f32, label.height(ctx) as f32);
+      graphics::draw(
+        ctx,
+        &label,
+        graphics::DrawParam::default()
+          .dest(center - label_size/2.0)
+          .color(graphics::WHITE)
+      ).unwrap();
+    }
+  }
+
+  fn camera_point(&self, distance: f32, point: Point2<f32>) -> Point2<f32> {
+    (point - (self.camera_offset * 1.0/distance)) * self.camera_zoom
+  }
+
+  fn camera_scale(&self, distance: f32, scale: Vector2<f32>) -> Vector2<f32> {
+    scale * self.camera_zoom
+  }
+}
+
+impl Scene for BattleScene {
+  fn update(&mut self, ctx: &mut Context, _world: &mut World) -> GameResult<SceneTransition> {
+    self.dt = timer::delta(ctx);
+
+    if self.sink.is_paused() { return Ok(SceneTransition::None); }
+
+    let time = self.clock.song_time_ms().round() as i32;
+
+    if let Some(ending) = &self.ending {
+      let elapsed = time - ending.start_time;
+      if elapsed >= ENDING_MS || self.sink.empty() {
+        return Ok(self.results(ending.outcome));
+      }
+      self.sink.set_volume(1.0 - (elapsed as f32)/(ENDING_MS as f32));
+      return Ok(SceneTransition::None);
+    }
+
+    if self.sink.empty() {
+      return Ok(self.results(BattleOutcome::Survived));
+    }
+
+    for input in mem::take(&mut self.direction_inputs) {
+      // Compensate for audio output and keyboard lag, as measured in calibration
+      let input_time = input.time - self.input_offset_ms;
+      // Presses go to the notes first; only one with no note near it picks a command
+      if let Some(judgement) = self.note_judge.judge_input(&self.pattern, input_time, input.direction, time) {
+        if self.pattern[judgement.note_idx].hold {
+          self.held_notes.insert(input.direction, judgement.note_idx);
+        }
+        self.score.record(&judgement);
+        self.last_judgement = Some(judgement);
+      } else if let Some(measure_idx) = self.open_command_measure(input_time) {
+        self.choose_command(measure_idx, input.direction);
+      }
+    }
+
+    for (direction, release_time) in self.releases.drain(..) {
+      if let Some(note_idx) = self.held_notes.remove(&direction) {
+        let release_time = release_time - self.input_offset_ms;
+        if let Some(judgement) = self.note_judge.judge_release(&self.pattern, note_idx, release_time, time) {
+          self.score.record(&judgement);
+          self.last_judgement = Some(judgement);
+        }
+      }
+    }
+
+    // Holding on well past the end of a hold note is judged as a late release
+    let hold_deadline_ms = (self.note_judge.windows().bad_ms as i32) + self.input_offset_ms;
+    let overdue: Vec<(NavDirection, usize)> = self.held_notes
+      .iter()
+      .filter(|(_, &note_idx)| time - hold_deadline_ms > self.pattern[note_idx].end_time() as i32)
+      .map(|(&direction, &note_idx)| (direction, note_idx))
+      .collect();
+    for (direction, note_idx) in overdue {
+      self.held_notes.remove(&direction);
+      if let Some(judgement) = self.note_judge.judge_release(&self.pattern, note_idx, time - self.input_offset_ms, time) {
+        self.score.record(&judgement);
+        self.last_judgement = Some(judgement);
+      }
+    }
+
+    for miss in self.note_judge.miss_passed_notes(&self.pattern, time - self.input_offset_ms, time) {
+      self.score.record(&miss);
+      self.last_judgement = Some(miss);
+    }
+
+    // Nothing in a measure can still be hit once its end is past the Bad window
+    let judged_until_ms = (time - self.input_offset_ms - (self.note_judge.windows().bad_ms as i32)) as f64;
+    loop {
+      let measure_idx = self.last_measure_judged.map_or(0, |idx| idx + 1);
+      if self.timing.measure_start_ms(measure_idx + 1) > judged_until_ms {
+        break;
+      }
+      self.resolve_judged_measure(measure_idx);
+      self.last_measure_judged = Some(measure_idx);
+    }
+
+    let current_measure_idx = self.timing.measure_idx_at_ms(time as f64);
+    let mut rng = rand::thread_rng();
+    for ai in self.enemy_ais.iter_mut().flatten() {
+      for (measure_idx, action) in ai.plan(current_measure_idx, &self.heroes, &self.enemies, &mut rng) {
+        self.actions.entry(measure_idx).or_default().push(action);
+      }
+    }
+
+    let beat_idx = self.timing.beat_at_ms(time as f64).floor() as i64;
+    while self.last_beat_ticked < beat_idx {
+      self.last_beat_ticked += 1;
+      self.tick_statuses(self.last_beat_ticked);
+    }
+
+    if self.heroes[self.command_window_hero].hp == 0 {
+      if let Some(next_hero) = self.next_living_hero(self.command_window_hero) {
+        self.command_window_hero = next_hero;
+      }
+    }
+
+    if let Some(outcome) = self.decided_outcome() {
+      self.ending = Some(BattleEnding { outcome: outcome, start_time: time });
+    }
+
+    Ok(SceneTransition::None)
+  }
+
+  fn draw(&mut self, ctx: &mut Context, _world: &World) -> GameResult<()> {
+    let window = graphics::screen_coordinates(ctx);
+    let time = self.clock.song_time_ms().round() as i32;
+
+    for bg_anim in self.bg_anims.iter() {
+      if let Some(img) = bg_anim.animation.get_frame(time.max(0) as u32, self.timing.ms_per_beat_at_ms(time as f64) as f32) {
+        graphics::draw(
+          ctx,
+          img,
+          graphics::DrawParam::default()
+            .dest(self.camera_point(bg_anim.distance, bg_anim.position))
+            .scale(self.camera_scale(bg_anim.distance, bg_anim.scale))
+        ).unwrap();
+      }
+    }
+
+    for (i, hero) in self.heroes.iter().enumerate() {
+      graphics::draw(
+        ctx,
+        match hero.character {
+          0 => &self.assets.char1,
+          1 => &self.assets.char2,
+          _ => panic!("Unknown hero character idx")
+        },
+        graphics::DrawParam::default()
+          .dest(self.camera_point(1.0, hero.position))
+          .scale(self.camera_scale(1.0, Vector2::new(1.0, 1.0)))
+          .color(if hero.hp == 0 { FALLEN_TINT } else { graphics::WHITE })
+      ).unwrap();
+
+      if self.command_window_hero == i && self.open_command_measure(time - self.input_offset_ms).is_some() {
+        self.draw_command_window(ctx, hero);
+      }
+
+      let hp_text = graphics::Text::new((format!("HP: {}/{}", hero.hp, hero.max_hp), self.assets.font, 30.0));
+      let hp_point = self.camera_point(1.0, hero.position) + self.camera_scale(1.0, Vector2::new(60.0, 400.0));
+      graphics::draw(
+        ctx,
+        &hp_text,
+        graphics::DrawParam::default()
+          .dest(hp_point)
+          .color(graphics::BLACK)
+      ).unwrap();
+      let icons_x = hp_text.width(ctx) as f32 + 10.0;
+      self.draw_status_icons(ctx, &hero.effects.statuses, hp_point + Vector2::new(icons_x, 0.0));
+    }
+
+    for enemy in &self.enemies {
+      graphics::draw(
+        ctx,
+        &self.assets.monster,
+        graphics::DrawParam::default()
+          .dest(self.camera_point(1.0, enemy.position))
+          .scale(self.camera_scale(1.0, Vector2::new(1.0, 1.0)))
+          .color(if enemy.hp == 0 { FALLEN_TINT } else { graphics::WHITE })
+      ).unwrap();
+
+      let hp_text = graphics::Text::new((format!("HP: {}/{}", enemy.hp, enemy.max_hp), self.assets.font, 30.0));
+      let hp_point = self.camera_point(1.0, enemy.position) + self.camera_scale(1.0, Vector2::new(200.0, 300.0));
+      graphics::draw(
+        ctx,
+        &hp_text,
+        graphics::DrawParam::default()
+          .dest(hp_point)
+          .color(graphics::BLACK)
+      ).unwrap();
+      let icons_x = hp_text.width(ctx) as f32 + 10.0;
+      self.draw_status_icons(ctx, &enemy.effects.statuses, hp_point + Vector2::new(icons_x, 0.0));
+    }
+
+    graphics::draw(
+      ctx,
+      &self.assets.music_bar,
+      graphics::DrawParam::default().dest(Point2::new(0.0, window.h - self.assets.music_bar_height))
+    ).unwrap();
+
+    let now_line_x = self.assets.now_line_x_offset;
+
+    graphics::draw(
+      ctx,
+      &self.assets.now_line,
+      graphics::DrawParam::default().dest(Point2::new(now_line_x, window.h - self.assets.music_bar_height))
+    ).unwrap();
+
+    let spacing_per_second = window.w/5.0;
+    let music_bar_min_pitch = 45;
+    let music_bar_max_pitch = 95;
+
+    let completion_offset_x: f32 = (time as f32)/1000.0 * spacing_per_second;
+
+    // FIXME: This could _definitely_ be done more efficiently and correctly
+    for measure_idx in 0..100 {
+      let measure_start_ms = self.timing.measure_start_ms(measure_idx);
+      let x = (measure_start_ms as f32)/1000.0 * spacing_per_second - completion_offset_x + now_line_x;
+      if x >= 0.0 && x <= window.w {
+        graphics::draw(
+          ctx,
+          &self.assets.measure_line,
+          graphics::DrawParam::default().dest(Point2::new(x, window.h - self.assets.music_bar_height))
+        ).unwrap();
+
+        // Indicators stack upwards when several things happen in one measure
+        let actions = self.actions.get(&measure_idx).map_or(&[][..], |actions| actions.as_slice());
+        if self.input_measures.contains(&measure_idx) {
+          graphics::draw(
+            ctx,
+            &self.assets.measure_action_indicator,
+            graphics::DrawParam::default()
+              .dest(Point2::new(x, window.h - (self.assets.music_bar_height + 20.0 * ((actions.len() + 1) as f32))))
+              .color(graphics::Color::from_rgba(128, 128, 128, 128))
+          ).unwrap();
+        }
+
+        for (i, action) in actions.iter().enumerate() {
+          let action_indicator_color = graphics::Color { a: 0.5, ..BattleScene::action_color(action) };
+
+          graphics::draw(
+            ctx,
+            &self.assets.measure_action_indicator,
+            graphics::DrawParam::default()
+              .dest(Point2::new(x, window.h - (self.assets.music_bar_height + 20.0 * ((i + 1) as f32))))
+              .color(action_indicator_color)
+          ).unwrap();
+
+          // Enemies wind up over their whole measure, so the player's notes there can block them
+          let (telegraph_start_time, action_time) = match action.src() {
+            ActionSource::Hero { .. } => (measure_start_ms as i32 - 400, measure_start_ms as i32),
+            ActionSource::Enemy { .. } => (measure_start_ms as i32, self.timing.measure_start_ms(measure_idx + 1) as i32),
+          };
+
+          let action = self.living_target(*action).unwrap_or(*action);
+          self.draw_action(ctx, &action, telegraph_start_time, action_time, time);
+        }
+      }
+    }
+
+    // FIXME: This could certainly be more efficient by not checking every single pattern note
+    for (note_idx, pattern_note) in self.pattern.iter().enumerate() {
+      let was_hit = match self.note_judge.grade(note_idx) {
+        None | Some(Grade::Miss) => false,
+        Some(_) => true,
+      };
+      // Notes that were hit leave the music bar, but a hold's bar stays until it's let go
+      let being_held = was_hit && pattern_note.hold && self.note_judge.tail_grade(note_idx).is_none();
+      if was_hit && !being_held {
+        continue;
+      }
+      let x = (pattern_note.time as f32)/1000.0 * spacing_per_second - completion_offset_x + now_line_x;
+      // Notes outside the pitch range sit on the edge of the music bar
+      let pitch_amt = (pattern_note.pitch.saturating_sub(music_bar_min_pitch) as f32)/((music_bar_max_pitch - music_bar_min_pitch) as f32);
+      let pitch_amt = pitch_amt.clamp(0.0, 1.0);
+      let y = window.h - self.assets.music_bar_height*pitch_amt;
+      let (mesh, arrow_color) = self.note_arrow(pattern_note);
+
+      if pattern_note.hold {
+        let bar_start_x = if being_held { x.max(now_line_x) } else { x };
+        let bar_end_x = (pattern_note.end_time() as f32)/1000.0 * spacing_per_second - completion_offset_x + now_line_x;
+        if bar_end_x > bar_start_x && bar_end_x >= 0.0 && bar_start_x <= window.w {
+          graphics::draw(
+            ctx,
+            &self.assets.hold_bar,
+            graphics::DrawParam::default()
+              .dest(Point2::new(bar_start_x, y))
+              .scale(Vector2::new(bar_end_x - bar_start_x, 1.0))
+              .color(graphics::Color { a: 0.5, ..arrow_color })
+          ).unwrap();
+        }
+      }
+
+      if !being_held && x >= (0.0 - self.assets.arrow_width) && x <= window.w {
+        graphics::draw(
+          ctx,
+          mesh,
+          graphics::DrawParam::default()
+            .dest(Point2::new(x, y))
+            .scale(if pattern_note.chord { Vector2::new(1.5, 1.5) } else { Vector2::new(1.0, 1.0) })
+        ).unwrap();
+      }
+    }
+
+    if let Some(judgement) = self.last_judgement {
+      if time - judgement.time < JUDGEMENT_DISPLAY_MS {
+        let label = if judgement.tail {
+          format!("{} release", judgement.grade.label())
+        } else {
+          judgement.grade.label().to_string()
+        };
+        let text = graphics::Text::new((label, self.assets.font, 40.0));
+        let x = now_line_x - (text.width(ctx) as f32)/2.0;
+        graphics::draw(
+          ctx,
+          &text,
+          graphics::DrawParam::default()
+            .dest(Point2::new(x, window.h - (self.assets.music_bar_height + 70.0)))
+            .color(judgement.grade.color())
+        ).unwrap();
+      }
+    }
+
+    let hud_text = graphics::Text::new((
+      format!("Combo {}  Max {}  {:.1}% {}", self.score.combo, self.score.max_combo, self.score.accuracy(), self.score.rank()),
+      self.assets.font,
+      30.0
+    ));
+    let x = window.w - (hud_text.width(ctx) as f32) - 20.0;
+    graphics::draw(
+      ctx,
+      &hud_text,
+      graphics::DrawParam::default()
+        .dest(Point2::new(x, 10.0))
+        .color(graphics::BLACK)
+    ).unwrap();
+
+    if let Some(ending) = &self.ending {
+      let text = graphics::Text::new((ending.outcome.label(), self.assets.font, 120.0));
+      let x = (window.w - text.width(ctx) as f32)/2.0;
+      let color = match ending.outcome {
+        BattleOutcome::Victory => graphics::Color::from_rgb(230, 170, 0),
+        _ => graphics::Color::from_rgb(200, 0, 0),
+      };
+      graphics::draw(
+        ctx,
+        &text,
+        graphics::DrawParam::default().dest(Point2::new(x, 200.0)).color(color)
+      ).unwrap();
+    }
+
+    if self.sink.is_paused() {
+      let text = graphics::Text::new(("Paused - press enter", self.assets.font, 75.0));
+      let x = (window.w - text.width(ctx) as f32)/2.0;
+      graphics::draw(
+        ctx,
+        &text,
+        graphics::DrawParam::default().dest(Point2::new(x, 50.0))
+      ).unwrap();
+    }
+
+    Ok(())
+  }
+
+  fn key_down_event(
+    &mut self,
+    _ctx: &mut Context,
+    _world: &mut World,
+    keycode: KeyCode,
+    _keymods: KeyMods,
+    repeat: bool
+  ) -> SceneTransition {
+    if repeat { return SceneTransition::None; }
+
+    if self.sink.is_paused() {
+      match keycode {
+        KeyCode::Escape => return SceneTransition::Pop,
+        KeyCode::Return => self.sink.play(),
+        _ => {}
+      }
+    } else {
+      match keycode {
+        KeyCode::Escape => return SceneTransition::Pop,
+        KeyCode::Return => self.sink.pause(),
+        KeyCode::Up | KeyCode::Right | KeyCode::Down | KeyCode::Left => {
+          self.direction_inputs.push(DirectionInput {
+            direction: match keycode {
+              KeyCode::Up => NavDirection::Up,
+              KeyCode::Down => NavDirection::Down,
+              KeyCode::Left => NavDirection::Left,
+              KeyCode::Right => NavDirection::Right,
+              _ => unreachable!()
+            },
+            time: self.clock.song_time_ms().round() as i32,
+          })
+        },
+        _ => {}
+      }
+    }
+
+    SceneTransition::None
+  }
+
+  fn key_up_event(&mut self, _ctx: &mut Context, _world: &mut World, keycode: KeyCode, _keymods: KeyMods) {
+    let direction = match keycode {
+      KeyCode::Up => NavDirection::Up,
+      KeyCode::Down => NavDirection::Down,
+      KeyCode::Left => NavDirection::Left,
+      KeyCode::Right => NavDirection::Right,
+      _ => return,
+    };
+    self.releases.push((direction, self.clock.song_time_ms().round() as i32));
+  }
+
+  fn mouse_button_down_event(
+      &mut self, 
+      _ctx: &mut Context, 
+      button: MouseButton, 
+      _x: f32, 
+      _y: f32
+  ) {
+    dbg!(button);
+  }
+
+  fn mouse_motion_event(
+    &mut self, 
+    _ctx: &mut Context, 
+    _x: f32, 
+    _y: f32, 
+    xrel: f32, 
+    yrel: f32
+  ) {
+    self.camera_offset += Vector2::new(xrel, yrel);
+  }
+
+  fn mouse_wheel_event(
+    &mut self,
+    _ctx: &mut Context, 
+    _x: f32, 
+    y: f32, 
+  ) {
+    self.camera_zoom += y * -0.001;
+    if self.camera_zoom < 0.3 {
+      self.camera_zoom = 0.3;
+    } else if self.camera_zoom > 3.0 {
+      self.camera_zoom = 3.0;
+    }
+  }
+}
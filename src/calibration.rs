@@ -0,0 +1,175 @@
+use std::{
+  f32::consts::PI,
+  time::Duration,
+};
+
+use ggez::{graphics, input::keyboard::{KeyCode, KeyMods}, Context, GameResult};
+use nalgebra::Point2;
+use rodio::{Sink, Source};
+
+use crate::counting_source::{CountingSource, PlaybackClock};
+use crate::scene::{Scene, SceneTransition, World};
+
+const CLICK_INTERVAL_MS: u32 = 600;
+const CLICK_LENGTH_MS: u32 = 30;
+const CLICK_PITCH_HZ: f32 = 1500.0;
+const SAMPLE_RATE: u32 = 44100;
+// Early taps are usually the player finding the beat, so they aren't counted
+const WARMUP_TAPS: usize = 4;
+const MEASURED_TAPS: usize = 16;
+
+// An endless mono click track, one click every CLICK_INTERVAL_MS starting at time zero
+struct Metronome {
+  frame: u32,
+}
+
+impl Iterator for Metronome {
+  type Item = f32;
+
+  fn next(&mut self) -> Option<f32> {
+    let interval_frames = SAMPLE_RATE * CLICK_INTERVAL_MS/1000;
+    let click_frames = SAMPLE_RATE * CLICK_LENGTH_MS/1000;
+    let frame_in_interval = self.frame % interval_frames;
+    self.frame = self.frame.wrapping_add(1);
+
+    if frame_in_interval >= click_frames {
+      return Some(0.0);
+    }
+    let t = (frame_in_interval as f32)/(SAMPLE_RATE as f32);
+    let decay = 1.0 - (frame_in_interval as f32)/(click_frames as f32);
+    Some((2.0 * PI * CLICK_PITCH_HZ * t).sin() * decay * 0.5)
+  }
+}
+
+impl Source for Metronome {
+  fn current_frame_len(&self) -> Option<usize> { None }
+  fn channels(&self) -> u16 { 1 }
+  fn sample_rate(&self) -> u32 { SAMPLE_RATE }
+  fn total_duration(&self) -> Option<Duration> { None }
+}
+
+pub struct CalibrationScene {
+  sink: Option<Sink>,
+  clock: Option<PlaybackClock>,
+  tap_offsets_ms: Vec<i32>,
+}
+
+impl CalibrationScene {
+  pub fn new() -> CalibrationScene {
+    CalibrationScene { sink: None, clock: None, tap_offsets_ms: Vec::new() }
+  }
+
+  fn start(&mut self) {
+    let sink = Sink::new(&rodio::default_output_device().unwrap());
+    let (source, clock) = CountingSource::new(Metronome { frame: 0 });
+    sink.append(source);
+    self.sink = Some(sink);
+    self.clock = Some(clock);
+    self.tap_offsets_ms.clear();
+  }
+
+  fn tap(&mut self) {
+    let time = match &mut self.clock {
+      Some(clock) => clock.now_ms().round() as i32,
+      None => return,
+    };
+    let interval = CLICK_INTERVAL_MS as i32;
+    // Offset to the nearest click, which may be the upcoming one if the tap was early
+    let mut offset = time % interval;
+    if offset > interval/2 {
+      offset -= interval;
+    }
+    self.tap_offsets_ms.push(offset);
+
+    if self.is_done() {
+      self.sink = None;
+      self.clock = None;
+    }
+  }
+
+  fn measured_offsets(&self) -> &[i32] {
+    if self.tap_offsets_ms.len() <= WARMUP_TAPS { &[] } else { &self.tap_offsets_ms[WARMUP_TAPS..] }
+  }
+
+  fn is_done(&self) -> bool {
+    self.measured_offsets().len() >= MEASURED_TAPS
+  }
+
+  fn average_offset_ms(&self) -> Option<i32> {
+    let offsets = self.measured_offsets();
+    if offsets.is_empty() {
+      return None;
+    }
+    Some((offsets.iter().sum::<i32>() as f32/offsets.len() as f32).round() as i32)
+  }
+}
+
+impl Scene for CalibrationScene {
+  fn update(&mut self, _ctx: &mut Context, _world: &mut World) -> GameResult<SceneTransition> {
+    Ok(SceneTransition::None)
+  }
+
+  fn draw(&mut self, ctx: &mut Context, world: &World) -> GameResult<()> {
+    graphics::draw(
+      ctx,
+      &graphics::Text::new(("Calibration", world.assets.font, 75.0)),
+      graphics::DrawParam::default().dest(Point2::new(100.0, 50.0)).color(graphics::BLACK)
+    )?;
+
+    let lines = if self.is_done() {
+      vec![
+        format!("Measured offset: {:+}ms", self.average_offset_ms().unwrap()),
+        "Press enter to save, or space to try again".to_owned(),
+      ]
+    } else if self.sink.is_some() {
+      let taps_left = WARMUP_TAPS + MEASURED_TAPS - self.tap_offsets_ms.len();
+      vec![
+        "Tap space along with the clicks".to_owned(),
+        format!("{} taps left", taps_left),
+      ]
+    } else {
+      vec![
+        format!("Current offset: {:+}ms", world.settings.input_offset_ms),
+        "Press space to start the metronome".to_owned(),
+      ]
+    };
+
+    for (i, line) in lines.into_iter().enumerate() {
+      graphics::draw(
+        ctx,
+        &graphics::Text::new((line, world.assets.font, 40.0)),
+        graphics::DrawParam::default()
+          .dest(Point2::new(100.0, 200.0 + (i as f32) * 60.0))
+          .color(graphics::BLACK)
+      )?;
+    }
+
+    Ok(())
+  }
+
+  fn key_down_event(
+    &mut self,
+    ctx: &mut Context,
+    world: &mut World,
+    keycode: KeyCode,
+    _keymods: KeyMods,
+    repeat: bool
+  ) -> SceneTransition {
+    if repeat { return SceneTransition::None; }
+
+    match keycode {
+      KeyCode::Escape => return SceneTransition::Pop,
+      KeyCode::Space if self.sink.is_some() => self.tap(),
+      KeyCode::Space => self.start(),
+      KeyCode::Return if self.is_done() => {
+        world.settings.input_offset_ms = self.average_offset_ms().unwrap();
+        if let Err(e) = world.settings.save(ctx) {
+          println!("Unable to save settings: {}", e);
+        }
+        return SceneTransition::Pop;
+      },
+      _ => {}
+    }
+    SceneTransition::None
+  }
+}
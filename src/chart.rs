@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use midly::{Smf, EventKind, MidiMessage};
+use serde::Deserialize;
+
+use crate::difficulty::{Difficulty, thin_notes};
+use crate::midi_events::absolute_events;
+use crate::quantize::{Quantization, QuantizeReport, quantize_times};
+use crate::timing::MidiTiming;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RelativePitch {
+  High,
+  Low
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NavDirection {
+  Up,
+  Right,
+  Down,
+  Left,
+}
+
+impl NavDirection {
+  // In two-lane mode, Up and Right both play high notes, and Down and Left low ones
+  pub fn relative_pitch(&self) -> RelativePitch {
+    match self {
+      NavDirection::Up | NavDirection::Right => RelativePitch::High,
+      NavDirection::Down | NavDirection::Left => RelativePitch::Low,
+    }
+  }
+}
+
+// How notes are mapped to keys
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum LaneMode {
+  // Any up key for a higher note, any down key for a lower one
+  #[default]
+  TwoLane,
+  // Up for a leap up, Right for a step up, Left for a step down, Down for a leap down
+  FourLane,
+}
+
+impl LaneMode {
+  pub fn accepts(&self, note: &PatternNote, direction: NavDirection) -> bool {
+    match self {
+      LaneMode::TwoLane => direction.relative_pitch() == note.relative_pitch,
+      LaneMode::FourLane => direction == note.lane,
+    }
+  }
+}
+
+// Notes held for at least this many beats become hold notes
+const HOLD_MIN_BEATS: f64 = 1.0;
+
+// Pitch changes of at least this many semitones are leaps rather than steps
+const LEAP_SEMITONES: u8 = 3;
+
+pub struct PatternNote {
+  pub time: u32,
+  // From note on to the matching note off, in ms
+  pub duration: u32,
+  pub pitch: u8,
+  pub relative_pitch: RelativePitch,
+  // The key that plays this note in four-lane mode
+  pub lane: NavDirection,
+  // Whether the key has to be held down for the whole duration
+  pub hold: bool,
+  // Whether this stands in for a chord, where more than one lane could have been played
+  pub chord: bool,
+}
+
+impl PatternNote {
+  pub fn end_time(&self) -> u32 {
+    self.time + self.duration
+  }
+}
+
+// Where to pull chart notes from: a whole track, or one MIDI channel across every
+// track (which is how single-track format 0 files are split into parts)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum NoteSource {
+  Track(usize),
+  Channel(u8),
+}
+
+impl NoteSource {
+  fn matches(&self, track: usize, channel: u8) -> bool {
+    match *self {
+      NoteSource::Track(idx) => idx == track,
+      NoteSource::Channel(ch) => ch == channel,
+    }
+  }
+}
+
+// Which note of a chord becomes the chart note
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum ChordVoice {
+  // The highest pitch, which usually carries the melody
+  Top,
+  Bass,
+  // The highest velocity
+  Loudest,
+}
+
+// How notes played together are reduced to one chart note
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ChordReduction {
+  // Notes starting within this many ticks of the first note of a chord join it, which
+  // also catches flams and parts on different tracks that are slightly out of line
+  pub tolerance_ticks: u64,
+  pub voice: ChordVoice,
+}
+
+impl Default for ChordReduction {
+  fn default() -> ChordReduction {
+    ChordReduction {
+      tolerance_ticks: 0,
+      voice: ChordVoice::Top,
+    }
+  }
+}
+
+struct SourceNote {
+  on_tick: u64,
+  off_tick: Option<u64>,
+  pitch: u8,
+  velocity: u8,
+}
+
+// Merges notes that start together and picks one voice from each chord, returning
+// (time, duration, pitch, is chord) for each
+fn reduce_chords(notes: &[SourceNote], timing: &MidiTiming, reduction: &ChordReduction) -> Vec<(f32, f32, u8, bool)> {
+  let mut reduced = Vec::new();
+  let mut chord_start_idx = 0;
+  while chord_start_idx < notes.len() {
+    let start_tick = notes[chord_start_idx].on_tick;
+    let chord_len = notes[chord_start_idx..]
+      .iter()
+      .take_while(|n| n.on_tick - start_tick <= reduction.tolerance_ticks)
+      .count();
+    let chord = &notes[chord_start_idx..chord_start_idx + chord_len];
+    chord_start_idx += chord_len;
+
+    let chosen = match reduction.voice {
+      ChordVoice::Top => chord.iter().max_by_key(|n| n.pitch),
+      ChordVoice::Bass => chord.iter().min_by_key(|n| n.pitch),
+      ChordVoice::Loudest => chord.iter().max_by_key(|n| n.velocity),
+    }.unwrap();
+    // The same pitch doubled across tracks is still just one note
+    let is_chord = chord.iter().any(|n| n.pitch != chosen.pitch);
+
+    let time = timing.tick_to_ms(start_tick) as f32;
+    let end_time = timing.tick_to_ms(chosen.off_tick.unwrap_or(chosen.on_tick)) as f32;
+    reduced.push((time, (end_time - time).max(0.0), chosen.pitch, is_chord));
+  }
+  reduced
+}
+
+pub fn get_pattern(
+  midi: &Smf,
+  timing: &MidiTiming,
+  sources: &[NoteSource],
+  reduction: &ChordReduction,
+  quantization: Option<&Quantization>,
+  difficulty: Difficulty,
+) -> (Vec<PatternNote>, Option<QuantizeReport>) {
+  // Notes still open are keyed by where they were played
+  let mut notes: Vec<SourceNote> = Vec::new();
+  let mut open_notes: HashMap<(usize, u8, u8), Vec<usize>> = HashMap::new();
+  for event in absolute_events(midi) {
+    let (channel, key, vel, is_on) = match event.kind {
+      EventKind::Midi{ channel, message: MidiMessage::NoteOn { key, vel } } => (channel.as_int(), key.as_int(), vel.as_int(), vel.as_int() > 0),
+      EventKind::Midi{ channel, message: MidiMessage::NoteOff { key, vel } } => (channel.as_int(), key.as_int(), vel.as_int(), false),
+      _ => continue,
+    };
+    if !sources.iter().any(|s| s.matches(event.track, channel)) {
+      continue;
+    }
+
+    let open_key = (event.track, channel, key);
+    if is_on {
+      open_notes.entry(open_key).or_default().push(notes.len());
+      notes.push(SourceNote { on_tick: event.tick, off_tick: None, pitch: key, velocity: vel });
+    } else if let Some(open) = open_notes.get_mut(&open_key) {
+      // Overlapping notes on one key are closed first in, first out
+      if !open.is_empty() {
+        let note_idx = open.remove(0);
+        notes[note_idx].off_tick = Some(event.tick);
+      }
+    }
+  }
+
+  let mut reduced = reduce_chords(&notes, timing, reduction);
+  let mut times: Vec<f32> = reduced.iter().map(|&(time, _, _, _)| time).collect();
+  let mut report = quantization.map(|q| quantize_times(&mut times, timing, q));
+  if let Some(report) = report.as_mut() {
+    // Notes keep their end times, so holds still end where they were played
+    for (note, &time) in reduced.iter_mut().zip(times.iter()) {
+      let end_time = note.0 + note.1;
+      *note = (time, (end_time - time).max(0.0), note.2, note.3);
+    }
+    // Notes snapped onto the same spot become one
+    let before_merge = reduced.len();
+    reduced.dedup_by(|later, earlier| later.0 == earlier.0);
+    report.merged = before_merge - reduced.len();
+  }
+  // A negative audio offset can put notes before the audio starts, where they can't be played
+  reduced.retain(|&(time, _, _, _)| time >= 0.0);
+  let times: Vec<f32> = reduced.iter().map(|&(time, _, _, _)| time).collect();
+  let keep = thin_notes(&times, timing, difficulty);
+
+  // Relative pitches and lanes are worked out after thinning, so they follow the notes that remain
+  let pattern = reduced
+    .into_iter()
+    .zip(keep)
+    .filter_map(|(note, keep)| if keep { Some(note) } else { None })
+    .scan((0, RelativePitch::High, NavDirection::Up), |(prior_pitch, prior_relative_pitch, prior_lane), (time, duration, pitch, chord)| {
+      // A repeated pitch stays in the same lane as the note before it
+      let relative_pitch = if pitch == *prior_pitch {
+        *prior_relative_pitch
+      } else if pitch > *prior_pitch {
+        RelativePitch::High
+      } else {
+        RelativePitch::Low
+      };
+      let lane = if pitch == *prior_pitch {
+        *prior_lane
+      } else if pitch >= prior_pitch.saturating_add(LEAP_SEMITONES) {
+        NavDirection::Up
+      } else if pitch > *prior_pitch {
+        NavDirection::Right
+      } else if pitch + LEAP_SEMITONES > *prior_pitch {
+        NavDirection::Left
+      } else {
+        NavDirection::Down
+      };
+      let ms_per_beat = timing.ms_per_beat_at_ms(time as f64);
+      let pn = PatternNote {
+        time: time as u32,
+        duration: duration as u32,
+        pitch: pitch,
+        relative_pitch: relative_pitch,
+        lane: lane,
+        hold: (duration as f64) >= ms_per_beat * HOLD_MIN_BEATS,
+        chord: chord,
+      };
+      *prior_relative_pitch = relative_pitch;
+      *prior_lane = lane;
+      *prior_pitch = pitch;
+      Some(pn)
+    })
+    .collect();
+  (pattern, report)
+}
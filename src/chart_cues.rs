@@ -0,0 +1,137 @@
+use midly::{Smf, EventKind, MetaMessage};
+
+use crate::encounter::{ActionSource, ActionTarget, CombatAction};
+use crate::midi_events::absolute_events;
+use crate::timing::MidiTiming;
+
+#[derive(Copy, Clone, Debug)]
+pub enum ChartCue {
+  Action(CombatAction),
+  // The player picks a hero action in this measure
+  HeroTurn,
+}
+
+// Reads battle cues that a composer wrote into the MIDI as marker, text or cue point events,
+// and returns them with the measure they fall in. Cues look like "enemy_attack" or
+// "enemy_attack 0 1" (enemy 0 attacks hero 1); see parse_cue for the full list. Text that
+// doesn't start with "hero_" or "enemy_" is left alone, so lyrics and credits don't matter.
+// If there's a control track, only its events are read.
+pub fn get_chart_cues(midi: &Smf, timing: &MidiTiming, control_track: Option<usize>) -> Result<Vec<(usize, ChartCue)>, String> {
+  let mut cues = Vec::new();
+  for event in absolute_events(midi) {
+    if control_track.is_some_and(|track| track != event.track) {
+      continue;
+    }
+    let text = match event.kind {
+      EventKind::Meta(MetaMessage::Marker(text)) => text,
+      EventKind::Meta(MetaMessage::Text(text)) => text,
+      EventKind::Meta(MetaMessage::CuePoint(text)) => text,
+      _ => continue,
+    };
+
+    let measure_idx = timing.measure_idx_at_tick(event.tick);
+    let text = String::from_utf8_lossy(text).trim().to_lowercase();
+    if let Some(cue) = parse_cue(&text).map_err(|e| format!("cue \"{}\" in measure {}: {}", text, measure_idx, e))? {
+      cues.push((measure_idx, cue));
+    }
+  }
+  Ok(cues)
+}
+
+// Cue words, with the optional numbers that can follow them (0 if left out):
+//   hero_turn
+//   hero_attack <hero> <enemy>, enemy_attack <enemy> <hero>
+//   hero_multi_hit <hero> <enemy> <hits>, enemy_multi_hit <enemy> <hero> <hits> (3 hits if left out)
+//   hero_defend <hero>, enemy_defend <enemy>
+//   hero_rest <hero>, enemy_rest <enemy>
+fn parse_cue(text: &str) -> Result<Option<ChartCue>, String> {
+  let mut words = text.split_whitespace();
+  let command = match words.next() {
+    Some(command) => command,
+    None => return Ok(None),
+  };
+  let (is_hero, verb) = if let Some(verb) = command.strip_prefix("hero_") {
+    (true, verb)
+  } else if let Some(verb) = command.strip_prefix("enemy_") {
+    (false, verb)
+  } else {
+    return Ok(None);
+  };
+
+  let args = words
+    .map(|word| word.parse::<usize>().map_err(|_| format!("\"{}\" is not a number", word)))
+    .collect::<Result<Vec<usize>, String>>()?;
+  let arg = |n: usize, default: usize| args.get(n).copied().unwrap_or(default);
+
+  let src = if is_hero { ActionSource::Hero { idx: arg(0, 0) } } else { ActionSource::Enemy { idx: arg(0, 0) } };
+  let tgt = if is_hero { ActionTarget::Enemy { idx: arg(1, 0) } } else { ActionTarget::Hero { idx: arg(1, 0) } };
+  let action = match verb {
+    "turn" if is_hero => return Ok(Some(ChartCue::HeroTurn)),
+    "attack" => CombatAction::Attack { src: src, tgt: tgt },
+    "multi_hit" => CombatAction::MultiHit { src: src, tgt: tgt, hits: arg(2, 3) as u32 },
+    "defend" => CombatAction::Defend { src: src },
+    "rest" => CombatAction::Rest { src: src },
+    _ => return Err(format!("unknown cue {}", command)),
+  };
+  Ok(Some(ChartCue::Action(action)))
+}
+
+#[cfg(test)]
+mod tests {
+  use midly::{Event, Format, Header, Timing};
+  use midly::number::{u15, u24, u28};
+
+  use super::*;
+  use crate::timing::get_timing;
+
+  #[test]
+  fn ignores_text_that_is_not_a_cue() {
+    assert!(matches!(parse_cue(""), Ok(None)));
+    assert!(matches!(parse_cue("verse 2"), Ok(None)));
+    assert!(matches!(parse_cue("la la la"), Ok(None)));
+  }
+
+  #[test]
+  fn parses_cues() {
+    assert!(matches!(parse_cue("hero_turn"), Ok(Some(ChartCue::HeroTurn))));
+    assert!(matches!(
+      parse_cue("enemy_attack 1 2"),
+      Ok(Some(ChartCue::Action(CombatAction::Attack {
+        src: ActionSource::Enemy { idx: 1 },
+        tgt: ActionTarget::Hero { idx: 2 },
+      })))
+    ));
+    assert!(matches!(
+      parse_cue("hero_multi_hit"),
+      Ok(Some(ChartCue::Action(CombatAction::MultiHit {
+        src: ActionSource::Hero { idx: 0 },
+        tgt: ActionTarget::Enemy { idx: 0 },
+        hits: 3,
+      })))
+    ));
+  }
+
+  #[test]
+  fn rejects_bad_cues() {
+    assert!(parse_cue("enemy_attack one").is_err());
+    assert!(parse_cue("hero_dance").is_err());
+    assert!(parse_cue("enemy_turn").is_err());
+  }
+
+  #[test]
+  fn cues_on_a_barline_start_that_measure() {
+    // 140 BPM, 480 ticks per beat, with a cue on the downbeat of measure 3
+    let ticks_per_measure = 4 * 480;
+    let track = vec![
+      Event { delta: u28::from(0), kind: EventKind::Meta(MetaMessage::Tempo(u24::from(60_000_000 / 140))) },
+      Event { delta: u28::from(3 * ticks_per_measure), kind: EventKind::Meta(MetaMessage::Marker(b"enemy_attack")) },
+      Event { delta: u28::from(0), kind: EventKind::Meta(MetaMessage::EndOfTrack) },
+    ];
+    let midi = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::from(480))), vec![track]).unwrap();
+    let timing = get_timing(&midi, 120.0, 0);
+
+    let cues = get_chart_cues(&midi, &timing, None).unwrap();
+    assert_eq!(cues.len(), 1);
+    assert_eq!(cues[0].0, 3);
+  }
+}
@@ -1,16 +1,84 @@
 use std::{
-  convert::TryInto,
-  sync::{Arc, atomic::{AtomicU32, Ordering}},
-  time::Duration,
+  sync::{Arc, atomic::{AtomicU32, AtomicU64, Ordering}},
+  time::{Duration, Instant},
 };
 
 use rodio::{Sample, Source};
 
+// How far past the last audio callback the clock may guess; roughly one output buffer
+const MAX_INTERPOLATION_MS: f64 = 50.0;
+
+// Written by the audio thread. Time is kept per run of frames at one sample rate,
+// so that a sample rate change mid-stream doesn't rescale the time already played.
+#[derive(Debug)]
+struct ClockShared {
+  frames: AtomicU64,
+  segment_start_frame: AtomicU64,
+  segment_start_us: AtomicU64,
+  sample_rate: AtomicU32,
+}
+
+impl ClockShared {
+  fn ms(&self) -> f64 {
+    let frames = self.frames.load(Ordering::Acquire);
+    let segment_start_frame = self.segment_start_frame.load(Ordering::Acquire);
+    let segment_start_us = self.segment_start_us.load(Ordering::Acquire);
+    let sample_rate = self.sample_rate.load(Ordering::Acquire);
+    let segment_frames = frames.saturating_sub(segment_start_frame);
+    (segment_start_us as f64)/1000.0 + (segment_frames as f64) * 1000.0/(sample_rate as f64)
+  }
+}
+
 #[derive(Clone, Debug)]
+pub struct PlaybackClock {
+  shared: Arc<ClockShared>,
+  last_audio_ms: f64,
+  last_audio_instant: Option<Instant>,
+  last_reported_ms: f64,
+}
+
+impl PlaybackClock {
+  // Position of the audio thread, which only moves forward once per audio callback
+  pub fn audio_ms(&self) -> f64 {
+    self.shared.ms()
+  }
+
+  // Smoothed position for the game loop: advances with the wall clock between
+  // audio callbacks, and never runs backwards
+  pub fn now_ms(&mut self) -> f64 {
+    let audio_ms = self.audio_ms();
+    let now = Instant::now();
+    if audio_ms != self.last_audio_ms || self.last_audio_instant.is_none() {
+      self.last_audio_ms = audio_ms;
+      self.last_audio_instant = Some(now);
+    }
+
+    let since_callback_ms = match self.last_audio_instant {
+      Some(instant) => now.duration_since(instant).as_secs_f64() * 1000.0,
+      None => 0.0,
+    };
+    // Before the first audio callback, nothing has played yet
+    let interpolated_ms = if audio_ms > 0.0 {
+      audio_ms + since_callback_ms.min(MAX_INTERPOLATION_MS)
+    } else {
+      0.0
+    };
+
+    self.last_reported_ms = self.last_reported_ms.max(interpolated_ms);
+    self.last_reported_ms
+  }
+}
+
+#[derive(Debug)]
 pub struct CountingSource<I> {
   input: I,
-  samples_till_next_ms: i32,
-  ms_elapsed: Arc<AtomicU32>
+  channels: u16,
+  sample_rate: u32,
+  samples_into_frame: u16,
+  frames: u64,
+  segment_start_frame: u64,
+  segment_start_us: u64,
+  shared: Arc<ClockShared>,
 }
 
 impl<I> CountingSource<I>
@@ -18,20 +86,48 @@ where
   I: Source,
   I::Item: Sample,
 {
-  pub fn new(source: I) -> (CountingSource<I>, Arc<AtomicU32>) {
-    let arc = Arc::new(AtomicU32::new(0));
-    let initial_sample_rate = source.sample_rate();
-    let mut counting_source = CountingSource {
+  pub fn new(source: I) -> (CountingSource<I>, PlaybackClock) {
+    let shared = Arc::new(ClockShared {
+      frames: AtomicU64::new(0),
+      segment_start_frame: AtomicU64::new(0),
+      segment_start_us: AtomicU64::new(0),
+      sample_rate: AtomicU32::new(source.sample_rate()),
+    });
+    let counting_source = CountingSource {
+      channels: source.channels(),
+      sample_rate: source.sample_rate(),
       input: source,
-      samples_till_next_ms: (initial_sample_rate/1000).try_into().unwrap(),
-      ms_elapsed: arc.clone()
+      samples_into_frame: 0,
+      frames: 0,
+      segment_start_frame: 0,
+      segment_start_us: 0,
+      shared: shared.clone(),
+    };
+    let clock = PlaybackClock {
+      shared: shared,
+      last_audio_ms: 0.0,
+      last_audio_instant: None,
+      last_reported_ms: 0.0,
     };
-    counting_source.reset_samples_till_next_ms();
-    (counting_source, arc)
+    (counting_source, clock)
   }
 
-  fn reset_samples_till_next_ms(&mut self) {
-    self.samples_till_next_ms = (self.input.sample_rate()/1000 * (self.input.channels() as u32)).try_into().unwrap();
+  fn start_segment_if_format_changed(&mut self) {
+    let sample_rate = self.input.sample_rate();
+    let channels = self.input.channels();
+    if sample_rate == self.sample_rate && channels == self.channels {
+      return;
+    }
+
+    let segment_frames = self.frames - self.segment_start_frame;
+    self.segment_start_us += segment_frames * 1_000_000/(self.sample_rate as u64);
+    self.segment_start_frame = self.frames;
+    self.sample_rate = sample_rate;
+    self.channels = channels;
+
+    self.shared.segment_start_frame.store(self.segment_start_frame, Ordering::Release);
+    self.shared.segment_start_us.store(self.segment_start_us, Ordering::Release);
+    self.shared.sample_rate.store(self.sample_rate, Ordering::Release);
   }
 }
 
@@ -46,11 +142,14 @@ where
   #[inline]
   fn next(&mut self) -> Option<I::Item> {
     let item = self.input.next();
-    if let Some(_) = item {
-      self.samples_till_next_ms -= 1;
-      if self.samples_till_next_ms <= 0 {
-        self.reset_samples_till_next_ms();
-        self.ms_elapsed.fetch_add(1, Ordering::Relaxed);
+    if item.is_some() {
+      self.samples_into_frame += 1;
+      if self.samples_into_frame >= self.channels {
+        self.samples_into_frame = 0;
+        self.frames += 1;
+        self.shared.frames.store(self.frames, Ordering::Release);
+        // Sources may only change format on a frame boundary
+        self.start_segment_if_format_changed();
       }
     }
     item
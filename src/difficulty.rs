@@ -0,0 +1,113 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::timing::MidiTiming;
+
+// How far off a beat (in beats) a note can be and still count as on it
+const BEAT_TOLERANCE: f64 = 0.05;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+  Easy,
+  Normal,
+  Hard,
+}
+
+pub const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+impl Difficulty {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Difficulty::Easy => "Easy",
+      Difficulty::Normal => "Normal",
+      Difficulty::Hard => "Hard",
+    }
+  }
+
+  // The weakest beat strength kept, the most notes in any one beat, and the shortest gap
+  // between notes in beats; Hard keeps every note
+  fn limits(&self) -> Option<(u8, usize, f64)> {
+    match self {
+      Difficulty::Easy => Some((1, 1, 1.0)),
+      Difficulty::Normal => Some((2, 2, 0.5)),
+      Difficulty::Hard => None,
+    }
+  }
+}
+
+// The measure a beat is in, and how many beats into it the beat falls. Looks a little
+// ahead so a note just early for a downbeat still lands in that measure.
+fn measure_position(timing: &MidiTiming, beat: f64) -> (usize, f64) {
+  let measure_idx = timing.measure_map.measure_idx_at_beat(beat + BEAT_TOLERANCE);
+  (measure_idx, beat - timing.measure_map.measure_start_beat(measure_idx))
+}
+
+// 0 for the start of a measure, 1 for its other beats, 2 for half beats, 3 for anything finer.
+// Beats are counted from the barline, so they stay put after measures of odd lengths.
+fn beat_strength(timing: &MidiTiming, beat: f64) -> u8 {
+  let near_whole = |beats: f64| (beats - beats.round()).abs() <= BEAT_TOLERANCE;
+  let (_, offset) = measure_position(timing, beat);
+  if offset.abs() <= BEAT_TOLERANCE {
+    0
+  } else if near_whole(offset) {
+    1
+  } else if near_whole(offset * 2.0) {
+    2
+  } else {
+    3
+  }
+}
+
+// Picks which of the notes at these times (sorted, in ms) to keep at the given difficulty.
+// Notes on stronger beats are considered first, so when a beat is too full or a run too
+// fast it's the offbeat notes that get dropped.
+pub fn thin_notes(times_ms: &[f32], timing: &MidiTiming, difficulty: Difficulty) -> Vec<bool> {
+  let (max_strength, max_per_beat, min_gap_beats) = match difficulty.limits() {
+    Some(limits) => limits,
+    None => return vec![true; times_ms.len()],
+  };
+
+  let beats: Vec<f64> = times_ms.iter().map(|&ms| timing.beat_at_ms(ms as f64)).collect();
+  let strengths: Vec<u8> = beats.iter().map(|&beat| beat_strength(timing, beat)).collect();
+  let mut order: Vec<usize> = (0..times_ms.len()).filter(|&idx| strengths[idx] <= max_strength).collect();
+  order.sort_by_key(|&idx| (strengths[idx], idx));
+
+  // Kept notes in thousandths of a beat, and how many were kept in each beat of each measure
+  let mut kept_positions: BTreeSet<i64> = BTreeSet::new();
+  let mut kept_per_beat: HashMap<(usize, i64), usize> = HashMap::new();
+  let min_gap = ((min_gap_beats - BEAT_TOLERANCE) * 1000.0) as i64;
+  let mut keep = vec![false; times_ms.len()];
+  for idx in order {
+    let (measure_idx, offset) = measure_position(timing, beats[idx]);
+    let beat_idx = (measure_idx, (offset + BEAT_TOLERANCE).floor() as i64);
+    let position = (beats[idx] * 1000.0).round() as i64;
+    let beat_count = kept_per_beat.entry(beat_idx).or_default();
+    if *beat_count >= max_per_beat || kept_positions.range(position - min_gap + 1..position + min_gap).next().is_some() {
+      continue;
+    }
+    *beat_count += 1;
+    kept_positions.insert(position);
+    keep[idx] = true;
+  }
+  keep
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::timing::{MeasureMap, TempoMap, TickUnit};
+
+  #[test]
+  fn beats_are_counted_from_the_barline() {
+    // A 3/8 measure, then 4/4 from beat 1.5, at 120 BPM
+    let timing = MidiTiming {
+      tempo_map: TempoMap::new(TickUnit::Beats { ticks_per_beat: 480.0 }, 500.0, &[]),
+      measure_map: MeasureMap::new(&[(0.0, 3, 3), (1.5, 4, 2)]),
+      audio_offset_ms: 0.0,
+    };
+    assert_eq!(beat_strength(&timing, 1.5), 0);
+    assert_eq!(beat_strength(&timing, 1.48), 0);
+    assert_eq!(beat_strength(&timing, 2.5), 1);
+    assert_eq!(beat_strength(&timing, 3.0), 2);
+    assert_eq!(beat_strength(&timing, 2.75), 3);
+  }
+}
@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use nalgebra::Point2;
+use serde::Deserialize;
+
+use crate::enemy_ai::EnemyBehavior;
+use crate::status::{StatusDuration, StatusEffects, StatusKind};
+
+const ENCOUNTERS_DIR: &str = "/encounters";
+
+// Where heroes stand when their encounter doesn't place them, in party order
+const PARTY_LAYOUT: [(f32, f32); 3] = [(260.0, 113.0), (40.0, 150.0), (470.0, 150.0)];
+
+// Scales attack power for the actions of every measure up to and including last_measure
+#[derive(Copy, Clone, Debug)]
+pub struct AttackModifier {
+  pub multiplier: f64,
+  pub last_measure: usize,
+}
+
+// Temporary effects of earlier actions on a hero or enemy
+#[derive(Clone, Debug, Default)]
+pub struct CombatEffects {
+  // Share of incoming damage blocked, and the last measure whose actions it applies to
+  pub guard: Option<(f64, usize)>,
+  pub attack_modifiers: Vec<AttackModifier>,
+  pub statuses: StatusEffects,
+}
+
+impl CombatEffects {
+  pub fn attack_multiplier(&self, measure_idx: usize) -> f64 {
+    self.attack_modifiers
+      .iter()
+      .filter(|m| measure_idx <= m.last_measure)
+      .map(|m| m.multiplier)
+      .product()
+  }
+
+  pub fn guard_reduction(&self, measure_idx: usize) -> f64 {
+    match self.guard {
+      Some((reduction, last_measure)) if measure_idx <= last_measure => reduction,
+      _ => 0.0,
+    }
+  }
+}
+
+pub struct HeroState {
+  pub character: usize,
+  pub position: Point2<f32>,
+  pub attack_power: u32,
+  pub hp: u32,
+  pub max_hp: u32,
+  pub effects: CombatEffects,
+}
+
+pub struct EnemyState {
+  pub position: Point2<f32>,
+  pub attack_power: u32,
+  pub hp: u32,
+  pub max_hp: u32,
+  pub effects: CombatEffects,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum ActionSource {
+  Hero { idx: usize },
+  Enemy { idx: usize },
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum ActionTarget {
+  Hero { idx: usize },
+  Enemy { idx: usize },
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum CombatAction {
+  Attack { src: ActionSource, tgt: ActionTarget },
+  // Recover some HP
+  Rest { src: ActionSource },
+  Heal { src: ActionSource, tgt: ActionTarget, amount: u32 },
+  // Blocks up to half the damage from actions resolved after it, through the next measure;
+  // weaker defends block less. A hero's defend covers enemy actions in its own measure and
+  // the next, but an enemy's only resolves once its measure is judged, so it covers just the
+  // hero actions of the next measure.
+  Defend { src: ActionSource },
+  // Multiplies the target's attack power for the next few measures
+  Buff { src: ActionSource, tgt: ActionTarget, multiplier: f64, measures: usize },
+  Debuff { src: ActionSource, tgt: ActionTarget, multiplier: f64, measures: usize },
+  // Several strikes, each weaker than a normal attack
+  MultiHit { src: ActionSource, tgt: ActionTarget, hits: u32 },
+  // Applies a status effect such as poison or stun
+  Inflict { src: ActionSource, tgt: ActionTarget, status: StatusKind, duration: StatusDuration },
+}
+
+impl CombatAction {
+  pub fn src(&self) -> ActionSource {
+    match *self {
+      CombatAction::Attack { src, .. } => src,
+      CombatAction::Rest { src } => src,
+      CombatAction::Heal { src, .. } => src,
+      CombatAction::Defend { src } => src,
+      CombatAction::Buff { src, .. } => src,
+      CombatAction::Debuff { src, .. } => src,
+      CombatAction::MultiHit { src, .. } => src,
+      CombatAction::Inflict { src, .. } => src,
+    }
+  }
+
+  pub fn tgt(&self) -> Option<ActionTarget> {
+    match *self {
+      CombatAction::Attack { tgt, .. } => Some(tgt),
+      CombatAction::Rest { .. } => None,
+      CombatAction::Heal { tgt, .. } => Some(tgt),
+      CombatAction::Defend { .. } => None,
+      CombatAction::Buff { tgt, .. } => Some(tgt),
+      CombatAction::Debuff { tgt, .. } => Some(tgt),
+      CombatAction::MultiHit { tgt, .. } => Some(tgt),
+      CombatAction::Inflict { tgt, .. } => Some(tgt),
+    }
+  }
+
+  // The same action aimed elsewhere; actions without a target are unchanged
+  pub fn with_tgt(self, new_tgt: ActionTarget) -> CombatAction {
+    match self {
+      CombatAction::Attack { src, .. } => CombatAction::Attack { src: src, tgt: new_tgt },
+      CombatAction::Heal { src, amount, .. } => CombatAction::Heal { src: src, tgt: new_tgt, amount: amount },
+      CombatAction::Buff { src, multiplier, measures, .. } => CombatAction::Buff { src: src, tgt: new_tgt, multiplier: multiplier, measures: measures },
+      CombatAction::Debuff { src, multiplier, measures, .. } => CombatAction::Debuff { src: src, tgt: new_tgt, multiplier: multiplier, measures: measures },
+      CombatAction::MultiHit { src, hits, .. } => CombatAction::MultiHit { src: src, tgt: new_tgt, hits: hits },
+      CombatAction::Inflict { src, status, duration, .. } => CombatAction::Inflict { src: src, tgt: new_tgt, status: status, duration: duration },
+      CombatAction::Rest { .. } | CombatAction::Defend { .. } => self,
+    }
+  }
+}
+
+impl From<ActionSource> for ActionTarget {
+  fn from(src: ActionSource) -> ActionTarget {
+    match src {
+      ActionSource::Hero { idx } => ActionTarget::Hero { idx: idx },
+      ActionSource::Enemy { idx } => ActionTarget::Enemy { idx: idx },
+    }
+  }
+}
+
+pub struct Encounter {
+  pub heroes: Vec<HeroState>,
+  pub enemies: Vec<EnemyState>,
+  pub actions: HashMap<usize, Vec<CombatAction>>,
+  // Measures in which the player picks a hero action for the following measure
+  pub input_measures: HashSet<usize>,
+  // Enemies without a behavior only do what the timeline says
+  pub enemy_behaviors: Vec<Option<EnemyBehavior>>,
+}
+
+// Attack power and max HP of each playable character
+fn character_stats(character: usize) -> Option<(u32, u32)> {
+  match character {
+    0 => Some((50, 180)),
+    1 => Some((40, 220)),
+    _ => None
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct HeroDef {
+  character: usize,
+  #[serde(default)]
+  position: Option<(f32, f32)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EnemyDef {
+  position: (f32, f32),
+  attack_power: u32,
+  hp: u32,
+  #[serde(default)]
+  behavior: Option<EnemyBehavior>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TimelineEntry {
+  measure: usize,
+  action: CombatAction,
+}
+
+// An encounter as written in a RON file under resources/encounters
+#[derive(Debug, Deserialize)]
+struct EncounterDef {
+  heroes: Vec<HeroDef>,
+  enemies: Vec<EnemyDef>,
+  #[serde(default)]
+  timeline: Vec<TimelineEntry>,
+  #[serde(default)]
+  input_measures: Vec<usize>,
+}
+
+impl Encounter {
+  pub fn load(ctx: &mut Context, name: &str) -> GameResult<Encounter> {
+    let path = format!("{}/{}.ron", ENCOUNTERS_DIR, name);
+    let file = filesystem::open(ctx, &path)?;
+    ron::de::from_reader(file)
+      .map_err(|e| e.to_string())
+      .and_then(Encounter::from_def)
+      .map_err(|e| GameError::ResourceLoadError(format!("Invalid encounter {}: {}", path, e)))
+  }
+
+  fn from_def(def: EncounterDef) -> Result<Encounter, String> {
+    if def.heroes.is_empty() || def.enemies.is_empty() {
+      return Err("needs at least one hero and one enemy".to_string());
+    }
+
+    let mut heroes = Vec::new();
+    for (idx, hero) in def.heroes.into_iter().enumerate() {
+      let (attack_power, max_hp) = character_stats(hero.character)
+        .ok_or_else(|| format!("unknown hero character {}", hero.character))?;
+      let position = hero.position
+        .or_else(|| PARTY_LAYOUT.get(idx).copied())
+        .ok_or_else(|| format!("hero {} needs a position, the party layout only has {} places", idx, PARTY_LAYOUT.len()))?;
+      heroes.push(HeroState {
+        character: hero.character,
+        position: Point2::new(position.0, position.1),
+        attack_power: attack_power,
+        hp: max_hp,
+        max_hp: max_hp,
+        effects: CombatEffects::default(),
+      });
+    }
+
+    let mut enemies = Vec::new();
+    let mut enemy_behaviors = Vec::new();
+    for (idx, enemy) in def.enemies.into_iter().enumerate() {
+      if let Some(behavior) = &enemy.behavior {
+        behavior.validate().map_err(|e| format!("enemy {} {}", idx, e))?;
+      }
+      enemies.push(EnemyState {
+        position: Point2::new(enemy.position.0, enemy.position.1),
+        attack_power: enemy.attack_power,
+        hp: enemy.hp,
+        max_hp: enemy.hp,
+        effects: CombatEffects::default(),
+      });
+      enemy_behaviors.push(enemy.behavior);
+    }
+
+    let mut encounter = Encounter {
+      heroes: heroes,
+      enemies: enemies,
+      actions: HashMap::new(),
+      input_measures: def.input_measures.into_iter().collect(),
+      enemy_behaviors: enemy_behaviors,
+    };
+    for entry in def.timeline {
+      encounter.add_action(entry.measure, entry.action)?;
+    }
+    Ok(encounter)
+  }
+
+  // Adds an action to the timeline, after checking that everyone it involves exists
+  pub fn add_action(&mut self, measure_idx: usize, action: CombatAction) -> Result<(), String> {
+    let src_ok = match action.src() {
+      ActionSource::Hero { idx } => idx < self.heroes.len(),
+      ActionSource::Enemy { idx } => idx < self.enemies.len(),
+    };
+    let tgt_ok = match action.tgt() {
+      Some(ActionTarget::Hero { idx }) => idx < self.heroes.len(),
+      Some(ActionTarget::Enemy { idx }) => idx < self.enemies.len(),
+      None => true,
+    };
+    if !src_ok {
+      return Err(format!("measure {} action has a nonexistent source {:?}", measure_idx, action.src()));
+    }
+    if !tgt_ok {
+      return Err(format!("measure {} action has a nonexistent target {:?}", measure_idx, action.tgt()));
+    }
+    match action {
+      CombatAction::Buff { measures: 0, .. } | CombatAction::Debuff { measures: 0, .. } => {
+        return Err(format!("measure {} action must last at least one measure", measure_idx));
+      },
+      CombatAction::MultiHit { hits: 0, .. } => {
+        return Err(format!("measure {} action must hit at least once", measure_idx));
+      },
+      CombatAction::Inflict { duration, .. } if duration.is_zero() => {
+        return Err(format!("measure {} status must last at least one beat", measure_idx));
+      },
+      _ => {}
+    }
+    self.actions.entry(measure_idx).or_default().push(action);
+    Ok(())
+  }
+}
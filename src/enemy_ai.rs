@@ -0,0 +1,233 @@
+use rand::{distributions::{Distribution, WeightedIndex}, seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::encounter::{ActionSource, ActionTarget, CombatAction, EnemyState, HeroState};
+use crate::status::{StatusDuration, StatusKind};
+
+// Something an enemy can do, before a target is picked for it
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum EnemyMove {
+  Attack,
+  MultiHit { hits: u32 },
+  Debuff { multiplier: f64, measures: usize },
+  Inflict { status: StatusKind, duration: StatusDuration },
+  // These affect the enemy itself
+  Rest,
+  Defend,
+  Buff { multiplier: f64, measures: usize },
+  // Heals whichever enemy is worst off
+  Heal { amount: u32 },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum MoveChoice {
+  // Cycles through the moves in order
+  Pattern(Vec<EnemyMove>),
+  // Picks at random, each move as likely as its weight
+  Weighted(Vec<(u32, EnemyMove)>),
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Targeting {
+  LowestHp,
+  Random,
+  // The hero that most recently hit this enemy, or the lowest HP hero if none has
+  LastAttacker,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnemyBehavior {
+  // Acts in first_measure and every `every` measures after it
+  pub first_measure: usize,
+  pub every: usize,
+  pub moves: MoveChoice,
+  pub targeting: Targeting,
+  // How many measures ahead each action is decided and shown on the music bar
+  pub telegraph_measures: usize,
+}
+
+impl EnemyMove {
+  // The same limits Encounter::add_action puts on the actions these turn into
+  fn validate(&self) -> Result<(), String> {
+    match *self {
+      EnemyMove::Buff { measures: 0, .. } | EnemyMove::Debuff { measures: 0, .. } => {
+        Err("move must last at least one measure".to_string())
+      },
+      EnemyMove::MultiHit { hits: 0 } => Err("move must hit at least once".to_string()),
+      EnemyMove::Inflict { duration, .. } if duration.is_zero() => {
+        Err("status must last at least one beat".to_string())
+      },
+      _ => Ok(()),
+    }
+  }
+}
+
+impl EnemyBehavior {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.every == 0 {
+      return Err("behavior must act at least every measure".to_string());
+    }
+    let moves: Vec<&EnemyMove> = match &self.moves {
+      MoveChoice::Pattern(moves) if moves.is_empty() => return Err("behavior pattern has no moves".to_string()),
+      MoveChoice::Weighted(moves) if moves.iter().all(|&(weight, _)| weight == 0) => {
+        return Err("behavior needs a move with a nonzero weight".to_string());
+      },
+      MoveChoice::Pattern(moves) => moves.iter().collect(),
+      MoveChoice::Weighted(moves) => moves.iter().map(|(_, enemy_move)| enemy_move).collect(),
+    };
+    for (idx, enemy_move) in moves.into_iter().enumerate() {
+      enemy_move.validate().map_err(|e| format!("behavior move {} {}", idx, e))?;
+    }
+    Ok(())
+  }
+
+  fn acts_in(&self, measure_idx: usize) -> bool {
+    measure_idx >= self.first_measure && (measure_idx - self.first_measure).is_multiple_of(self.every)
+  }
+}
+
+// Decides one enemy's actions as the fight goes on
+#[derive(Clone, Debug)]
+pub struct EnemyAi {
+  enemy_idx: usize,
+  behavior: EnemyBehavior,
+  pattern_pos: usize,
+  planned_through: Option<usize>,
+  pub last_attacker: Option<usize>,
+}
+
+impl EnemyAi {
+  pub fn new(enemy_idx: usize, behavior: EnemyBehavior) -> EnemyAi {
+    EnemyAi {
+      enemy_idx: enemy_idx,
+      behavior: behavior,
+      pattern_pos: 0,
+      planned_through: None,
+      last_attacker: None,
+    }
+  }
+
+  // Picks actions for every measure up to the telegraph lead past the current one, returning
+  // (measure, action) pairs for the measures this enemy acts in
+  pub fn plan<R: Rng>(
+    &mut self,
+    current_measure_idx: usize,
+    heroes: &[HeroState],
+    enemies: &[EnemyState],
+    rng: &mut R,
+  ) -> Vec<(usize, CombatAction)> {
+    let mut planned = Vec::new();
+    if enemies[self.enemy_idx].hp == 0 {
+      return planned;
+    }
+
+    let plan_until = current_measure_idx + self.behavior.telegraph_measures;
+    let plan_from = self.planned_through.map_or(current_measure_idx, |idx| idx + 1);
+    for measure_idx in plan_from..=plan_until {
+      if self.behavior.acts_in(measure_idx) {
+        let enemy_move = self.next_move(rng);
+        if let Some(action) = self.action_for(enemy_move, heroes, enemies, rng) {
+          planned.push((measure_idx, action));
+        }
+      }
+    }
+    self.planned_through = Some(plan_until);
+    planned
+  }
+
+  fn next_move<R: Rng>(&mut self, rng: &mut R) -> EnemyMove {
+    match &self.behavior.moves {
+      MoveChoice::Pattern(moves) => {
+        let enemy_move = moves[self.pattern_pos % moves.len()];
+        self.pattern_pos += 1;
+        enemy_move
+      },
+      MoveChoice::Weighted(moves) => {
+        let weights = WeightedIndex::new(moves.iter().map(|&(weight, _)| weight)).unwrap();
+        moves[weights.sample(rng)].1
+      },
+    }
+  }
+
+  fn hero_target<R: Rng>(&self, heroes: &[HeroState], rng: &mut R) -> Option<ActionTarget> {
+    let living: Vec<usize> = (0..heroes.len()).filter(|&idx| heroes[idx].hp > 0).collect();
+    let lowest_hp = || living.iter().copied().min_by_key(|&idx| heroes[idx].hp);
+    let idx = match self.behavior.targeting {
+      Targeting::LowestHp => lowest_hp(),
+      Targeting::Random => living.choose(rng).copied(),
+      Targeting::LastAttacker => self.last_attacker.filter(|&idx| heroes[idx].hp > 0).or_else(lowest_hp),
+    }?;
+    Some(ActionTarget::Hero { idx: idx })
+  }
+
+  fn action_for<R: Rng>(
+    &self,
+    enemy_move: EnemyMove,
+    heroes: &[HeroState],
+    enemies: &[EnemyState],
+    rng: &mut R,
+  ) -> Option<CombatAction> {
+    let src = ActionSource::Enemy { idx: self.enemy_idx };
+    let own_tgt = ActionTarget::Enemy { idx: self.enemy_idx };
+    let action = match enemy_move {
+      EnemyMove::Attack => CombatAction::Attack { src: src, tgt: self.hero_target(heroes, rng)? },
+      EnemyMove::MultiHit { hits } => CombatAction::MultiHit { src: src, tgt: self.hero_target(heroes, rng)?, hits: hits },
+      EnemyMove::Debuff { multiplier, measures } => CombatAction::Debuff {
+        src: src,
+        tgt: self.hero_target(heroes, rng)?,
+        multiplier: multiplier,
+        measures: measures,
+      },
+      EnemyMove::Inflict { status, duration } => CombatAction::Inflict {
+        src: src,
+        tgt: self.hero_target(heroes, rng)?,
+        status: status,
+        duration: duration,
+      },
+      EnemyMove::Rest => CombatAction::Rest { src: src },
+      EnemyMove::Defend => CombatAction::Defend { src: src },
+      EnemyMove::Buff { multiplier, measures } => CombatAction::Buff {
+        src: src,
+        tgt: own_tgt,
+        multiplier: multiplier,
+        measures: measures,
+      },
+      EnemyMove::Heal { amount } => {
+        let most_hurt = (0..enemies.len())
+          .filter(|&idx| enemies[idx].hp > 0)
+          .min_by(|&a, &b| {
+            let fraction = |idx: usize| (enemies[idx].hp as f64)/(enemies[idx].max_hp as f64);
+            fraction(a).partial_cmp(&fraction(b)).unwrap()
+          })?;
+        CombatAction::Heal { src: src, tgt: ActionTarget::Enemy { idx: most_hurt }, amount: amount }
+      },
+    };
+    Some(action)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn behavior(moves: MoveChoice) -> EnemyBehavior {
+    EnemyBehavior { first_measure: 0, every: 1, moves: moves, targeting: Targeting::LowestHp, telegraph_measures: 1 }
+  }
+
+  #[test]
+  fn validates_move_parameters() {
+    let ok = MoveChoice::Pattern(vec![EnemyMove::Attack, EnemyMove::MultiHit { hits: 2 }]);
+    assert!(behavior(ok).validate().is_ok());
+
+    let bad_moves = [
+      EnemyMove::MultiHit { hits: 0 },
+      EnemyMove::Buff { multiplier: 1.5, measures: 0 },
+      EnemyMove::Debuff { multiplier: 0.5, measures: 0 },
+      EnemyMove::Inflict { status: StatusKind::Stun, duration: StatusDuration::Beats(0) },
+    ];
+    for &bad_move in bad_moves.iter() {
+      assert!(behavior(MoveChoice::Pattern(vec![EnemyMove::Attack, bad_move])).validate().is_err());
+      assert!(behavior(MoveChoice::Weighted(vec![(1, EnemyMove::Attack), (0, bad_move)])).validate().is_err());
+    }
+  }
+}
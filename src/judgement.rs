@@ -0,0 +1,228 @@
+use std::convert::TryFrom;
+
+use ggez::graphics::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::chart::{LaneMode, NavDirection, PatternNote};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Grade {
+  Perfect,
+  Great,
+  Good,
+  Bad,
+  Miss,
+}
+
+impl Grade {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Grade::Perfect => "Perfect",
+      Grade::Great => "Great",
+      Grade::Good => "Good",
+      Grade::Bad => "Bad",
+      Grade::Miss => "Miss",
+    }
+  }
+
+  // Share of a Perfect that this grade is worth towards accuracy
+  pub fn weight(&self) -> f64 {
+    match self {
+      Grade::Perfect => 1.0,
+      Grade::Great => 0.8,
+      Grade::Good => 0.5,
+      Grade::Bad => 0.2,
+      Grade::Miss => 0.0,
+    }
+  }
+
+  pub fn color(&self) -> Color {
+    match self {
+      Grade::Perfect => Color::from_rgb(230, 170, 0),
+      Grade::Great => Color::from_rgb(0, 160, 60),
+      Grade::Good => Color::from_rgb(0, 90, 220),
+      Grade::Bad => Color::from_rgb(130, 60, 160),
+      Grade::Miss => Color::from_rgb(200, 0, 0),
+    }
+  }
+}
+
+// How far from a note (in either direction) an input may land and still get each grade
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JudgementWindows {
+  pub perfect_ms: u32,
+  pub great_ms: u32,
+  pub good_ms: u32,
+  pub bad_ms: u32,
+}
+
+impl Default for JudgementWindows {
+  fn default() -> JudgementWindows {
+    JudgementWindows {
+      perfect_ms: 40,
+      great_ms: 80,
+      good_ms: 130,
+      bad_ms: 180,
+    }
+  }
+}
+
+impl JudgementWindows {
+  pub fn grade(&self, offset_ms: i32) -> Option<Grade> {
+    let distance = offset_ms.unsigned_abs();
+    if distance <= self.perfect_ms {
+      Some(Grade::Perfect)
+    } else if distance <= self.great_ms {
+      Some(Grade::Great)
+    } else if distance <= self.good_ms {
+      Some(Grade::Good)
+    } else if distance <= self.bad_ms {
+      Some(Grade::Bad)
+    } else {
+      None
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Judgement {
+  pub note_idx: usize,
+  // Whether this judges the release at the end of a hold note rather than the press
+  pub tail: bool,
+  pub grade: Grade,
+  // Input time minus note time; None for misses and releases
+  pub offset_ms: Option<i32>,
+  // Song time at which the judgement was made
+  pub time: i32,
+}
+
+// Tracks which notes of a pattern have been judged. The pattern must be sorted by time.
+#[derive(Clone, Debug)]
+pub struct NoteJudge {
+  windows: JudgementWindows,
+  lane_mode: LaneMode,
+  grades: Vec<Option<Grade>>,
+  // Release grades, only ever set for hold notes
+  tail_grades: Vec<Option<Grade>>,
+  // Every note before this one has been judged
+  first_unjudged_idx: usize,
+}
+
+impl NoteJudge {
+  pub fn new(windows: JudgementWindows, lane_mode: LaneMode, pattern: &[PatternNote]) -> NoteJudge {
+    NoteJudge {
+      windows: windows,
+      lane_mode: lane_mode,
+      grades: vec![None; pattern.len()],
+      tail_grades: vec![None; pattern.len()],
+      first_unjudged_idx: 0,
+    }
+  }
+
+  pub fn windows(&self) -> &JudgementWindows {
+    &self.windows
+  }
+
+  pub fn grade(&self, note_idx: usize) -> Option<Grade> {
+    self.grades[note_idx]
+  }
+
+  pub fn tail_grade(&self, note_idx: usize) -> Option<Grade> {
+    self.tail_grades[note_idx]
+  }
+
+  // Mean grade weight of the judged notes in [start_ms, end_ms), or None if there are none.
+  // Hold note releases count towards wherever the hold ends.
+  pub fn accuracy_between(&self, pattern: &[PatternNote], start_ms: f64, end_ms: f64) -> Option<f64> {
+    let in_range = |time: u32| (time as f64) >= start_ms && (time as f64) < end_ms;
+    let heads = pattern
+      .iter()
+      .zip(self.grades.iter())
+      .filter(|(pn, _)| in_range(pn.time));
+    let tails = pattern
+      .iter()
+      .zip(self.tail_grades.iter())
+      .filter(|(pn, _)| pn.hold && in_range(pn.end_time()));
+    let weights: Vec<f64> = heads
+      .chain(tails)
+      .filter_map(|(_, grade)| grade.map(|g| g.weight()))
+      .collect();
+    if weights.is_empty() {
+      return None;
+    }
+    Some(weights.iter().sum::<f64>()/(weights.len() as f64))
+  }
+
+  // Grades an input against the nearest unjudged note within the Bad window, consuming
+  // that note. Pressing the wrong key on time still only earns a Bad.
+  pub fn judge_input(
+    &mut self,
+    pattern: &[PatternNote],
+    input_time: i32,
+    direction: NavDirection,
+    now: i32,
+  ) -> Option<Judgement> {
+    let nearest = pattern
+      .iter()
+      .enumerate()
+      .skip(self.first_unjudged_idx)
+      .filter(|(idx, _)| self.grades[*idx].is_none())
+      .map(|(idx, pn)| (idx, pn, input_time - i32::try_from(pn.time).unwrap()))
+      .take_while(|(_, _, offset_ms)| *offset_ms >= -(self.windows.bad_ms as i32))
+      .min_by_key(|(_, _, offset_ms)| offset_ms.abs());
+
+    let (note_idx, note, offset_ms) = nearest?;
+    let grade = match self.windows.grade(offset_ms)? {
+      _ if !self.lane_mode.accepts(note, direction) => Grade::Bad,
+      grade => grade,
+    };
+
+    self.grades[note_idx] = Some(grade);
+    self.advance_first_unjudged();
+    Some(Judgement { note_idx: note_idx, tail: false, grade: grade, offset_ms: Some(offset_ms), time: now })
+  }
+
+  // Grades letting go of a hold note whose press was already judged. Letting go too early
+  // breaks the hold; holding on too long (release_time well past the end) only earns a Bad.
+  pub fn judge_release(&mut self, pattern: &[PatternNote], note_idx: usize, release_time: i32, now: i32) -> Option<Judgement> {
+    if !pattern[note_idx].hold || self.grades[note_idx].is_none() || self.tail_grades[note_idx].is_some() {
+      return None;
+    }
+    let offset_ms = release_time - i32::try_from(pattern[note_idx].end_time()).unwrap();
+    let grade = if offset_ms < -(self.windows.bad_ms as i32) {
+      Grade::Miss
+    } else {
+      self.windows.grade(offset_ms).unwrap_or(Grade::Bad)
+    };
+    self.tail_grades[note_idx] = Some(grade);
+    Some(Judgement { note_idx: note_idx, tail: true, grade: grade, offset_ms: None, time: now })
+  }
+
+  // Misses every unjudged note that can no longer be hit at the given time
+  pub fn miss_passed_notes(&mut self, pattern: &[PatternNote], input_time: i32, now: i32) -> Vec<Judgement> {
+    let mut misses = Vec::new();
+    for (idx, pn) in pattern.iter().enumerate().skip(self.first_unjudged_idx) {
+      if i32::try_from(pn.time).unwrap() + (self.windows.bad_ms as i32) >= input_time {
+        break;
+      }
+      if self.grades[idx].is_none() {
+        self.grades[idx] = Some(Grade::Miss);
+        misses.push(Judgement { note_idx: idx, tail: false, grade: Grade::Miss, offset_ms: None, time: now });
+        // A hold that was never pressed can't be released either
+        if pn.hold {
+          self.tail_grades[idx] = Some(Grade::Miss);
+          misses.push(Judgement { note_idx: idx, tail: true, grade: Grade::Miss, offset_ms: None, time: now });
+        }
+      }
+    }
+    self.advance_first_unjudged();
+    misses
+  }
+
+  fn advance_first_unjudged(&mut self) {
+    while self.first_unjudged_idx < self.grades.len() && self.grades[self.first_unjudged_idx].is_some() {
+      self.first_unjudged_idx += 1;
+    }
+  }
+}
@@ -0,0 +1,29 @@
+use midly::{Smf, Format, EventKind};
+
+#[derive(Copy, Clone, Debug)]
+pub struct AbsoluteEvent<'a> {
+  pub tick: u64,
+  pub track: usize,
+  pub kind: EventKind<'a>,
+}
+
+// Flattens every track into one list of events sorted by absolute tick. Tracks in a
+// sequential (format 2) file are laid end to end rather than played together.
+pub fn absolute_events<'a>(midi: &Smf<'a>) -> Vec<AbsoluteEvent<'a>> {
+  let mut events = Vec::new();
+  let mut track_start_tick: u64 = 0;
+  for (track_idx, track) in midi.tracks.iter().enumerate() {
+    let mut tick = track_start_tick;
+    for event in track {
+      tick += event.delta.as_int() as u64;
+      events.push(AbsoluteEvent { tick: tick, track: track_idx, kind: event.kind });
+    }
+    if let Format::Sequential = midi.header.format {
+      track_start_tick = tick;
+    }
+  }
+
+  // Stable sort, so simultaneous events keep their file order
+  events.sort_by_key(|e| e.tick);
+  events
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::timing::MidiTiming;
+
+// Shifts smaller than this don't count as moving a note
+const MOVED_MIN_MS: f64 = 0.5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum Subdivision {
+  Quarter,
+  Eighth,
+  Sixteenth,
+  QuarterTriplet,
+  EighthTriplet,
+}
+
+impl Subdivision {
+  fn beats(&self) -> f64 {
+    match self {
+      Subdivision::Quarter => 1.0,
+      Subdivision::Eighth => 0.5,
+      Subdivision::Sixteenth => 0.25,
+      Subdivision::QuarterTriplet => 2.0/3.0,
+      Subdivision::EighthTriplet => 1.0/3.0,
+    }
+  }
+}
+
+fn default_strength() -> f64 { 1.0 }
+
+// Snaps chart notes towards a beat grid, for MIDI that was played in by hand
+#[derive(Clone, Debug, Deserialize)]
+pub struct Quantization {
+  pub subdivision: Subdivision,
+  // 1.0 moves notes all the way onto the grid, 0.5 halfway there
+  #[serde(default = "default_strength")]
+  pub strength: f64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct QuantizeReport {
+  pub notes: usize,
+  pub moved: usize,
+  pub mean_shift_ms: f64,
+  pub max_shift_ms: f64,
+  // Notes that landed on the same spot as the note before them and were merged into it
+  pub merged: usize,
+}
+
+impl fmt::Display for QuantizeReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "moved {} of {} notes, by {:.1} ms on average and {:.1} ms at most; merged {}",
+      self.moved,
+      self.notes,
+      self.mean_shift_ms,
+      self.max_shift_ms,
+      self.merged
+    )
+  }
+}
+
+// Moves each time (in ms) towards its nearest grid line. Since every time moves the same
+// share of the way, times that were in order stay in order.
+pub fn quantize_times(times_ms: &mut [f32], timing: &MidiTiming, quantization: &Quantization) -> QuantizeReport {
+  let grid = quantization.subdivision.beats();
+  let strength = quantization.strength.clamp(0.0, 1.0);
+  let mut report = QuantizeReport { notes: times_ms.len(), ..QuantizeReport::default() };
+  let mut total_shift_ms = 0.0;
+  for time in times_ms.iter_mut() {
+    let beat = timing.beat_at_ms(*time as f64);
+    // The grid restarts at every barline, so measures of odd lengths don't throw it off
+    let measure_idx = timing.measure_map.measure_idx_at_beat(beat);
+    let measure_start = timing.measure_map.measure_start_beat(measure_idx);
+    let next_measure_start = timing.measure_map.measure_start_beat(measure_idx + 1);
+    let grid_beat = measure_start + ((beat - measure_start)/grid).round() * grid;
+    let grid_beat = if next_measure_start - beat < (grid_beat - beat).abs() { next_measure_start } else { grid_beat };
+    let new_time = timing.beat_to_ms(beat + (grid_beat - beat) * strength);
+    let shift_ms = (new_time - *time as f64).abs();
+    if shift_ms >= MOVED_MIN_MS {
+      report.moved += 1;
+      total_shift_ms += shift_ms;
+      report.max_shift_ms = report.max_shift_ms.max(shift_ms);
+    }
+    *time = new_time as f32;
+  }
+  if report.moved > 0 {
+    report.mean_shift_ms = total_shift_ms/(report.moved as f64);
+  }
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::timing::{MeasureMap, TempoMap, TickUnit};
+
+  #[test]
+  fn grid_starts_at_each_barline() {
+    // A 3/8 measure, then 4/4 from beat 1.5, at 120 BPM
+    let timing = MidiTiming {
+      tempo_map: TempoMap::new(TickUnit::Beats { ticks_per_beat: 480.0 }, 500.0, &[]),
+      measure_map: MeasureMap::new(&[(0.0, 3, 3), (1.5, 4, 2)]),
+      audio_offset_ms: 0.0,
+    };
+    let quantization = Quantization { subdivision: Subdivision::Quarter, strength: 1.0 };
+    // A little after the second beat of the 4/4 measure, and just before the barline
+    let mut times = [timing.beat_to_ms(2.6) as f32, timing.beat_to_ms(1.4) as f32];
+    quantize_times(&mut times, &timing, &quantization);
+    assert!((timing.beat_at_ms(times[0] as f64) - 2.5).abs() < 0.001);
+    assert!((timing.beat_at_ms(times[1] as f64) - 1.5).abs() < 0.001);
+  }
+}
@@ -0,0 +1,113 @@
+use ggez::{graphics, input::keyboard::{KeyCode, KeyMods}, Context, GameResult};
+use nalgebra::Point2;
+
+use crate::scene::{Scene, SceneTransition, World};
+use crate::score::{GRADES, ScoreState};
+use crate::song::SongManifest;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BattleOutcome {
+  Victory,
+  Defeat,
+  // The song ended with both sides still standing
+  Survived,
+}
+
+impl BattleOutcome {
+  pub fn label(&self) -> &'static str {
+    match self {
+      BattleOutcome::Victory => "Victory!",
+      BattleOutcome::Defeat => "Defeat...",
+      BattleOutcome::Survived => "Survived",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct BattleSummary {
+  pub outcome: BattleOutcome,
+  pub damage_dealt: u32,
+  pub damage_taken: u32,
+  pub score: ScoreState,
+}
+
+pub struct ResultsScene {
+  song_title: String,
+  summary: BattleSummary,
+}
+
+impl ResultsScene {
+  pub fn new(song: &SongManifest, summary: BattleSummary) -> ResultsScene {
+    ResultsScene { song_title: song.title.clone(), summary: summary }
+  }
+
+  fn battle_lines(&self) -> Vec<String> {
+    let score = &self.summary.score;
+    let mut lines = vec![
+      self.summary.outcome.label().to_string(),
+      format!("Damage dealt {}", self.summary.damage_dealt),
+      format!("Damage taken {}", self.summary.damage_taken),
+      format!("Rank {}   Accuracy {:.2}%", score.rank(), score.accuracy()),
+      format!("Max combo {}", score.max_combo),
+    ];
+    match (score.mean_offset_ms(), score.offset_std_dev_ms()) {
+      (Some(mean), Some(std_dev)) => lines.push(format!("Offset {:+.1}ms (std dev {:.1}ms)", mean, std_dev)),
+      _ => lines.push("No notes hit".to_string()),
+    }
+    lines
+  }
+
+  fn judgement_lines(&self) -> Vec<String> {
+    GRADES
+      .iter()
+      .map(|&grade| format!("{}: {}", grade.label(), self.summary.score.count(grade)))
+      .collect()
+  }
+}
+
+impl Scene for ResultsScene {
+  fn update(&mut self, _ctx: &mut Context, _world: &mut World) -> GameResult<SceneTransition> {
+    Ok(SceneTransition::None)
+  }
+
+  fn draw(&mut self, ctx: &mut Context, world: &World) -> GameResult<()> {
+    graphics::draw(
+      ctx,
+      &graphics::Text::new((self.song_title.as_str(), world.assets.font, 75.0)),
+      graphics::DrawParam::default().dest(Point2::new(100.0, 50.0)).color(graphics::BLACK)
+    )?;
+
+    let columns = [(100.0, self.battle_lines()), (800.0, self.judgement_lines())];
+    for (x, lines) in columns.iter() {
+      for (i, line) in lines.iter().enumerate() {
+        graphics::draw(
+          ctx,
+          &graphics::Text::new((line.as_str(), world.assets.font, 40.0)),
+          graphics::DrawParam::default().dest(Point2::new(*x, 170.0 + 55.0 * (i as f32))).color(graphics::BLACK)
+        )?;
+      }
+    }
+
+    graphics::draw(
+      ctx,
+      &graphics::Text::new(("Press enter to continue", world.assets.font, 40.0)),
+      graphics::DrawParam::default().dest(Point2::new(100.0, 620.0)).color(graphics::BLACK)
+    )
+  }
+
+  fn key_down_event(
+    &mut self,
+    _ctx: &mut Context,
+    _world: &mut World,
+    keycode: KeyCode,
+    _keymods: KeyMods,
+    repeat: bool
+  ) -> SceneTransition {
+    if repeat { return SceneTransition::None; }
+
+    match keycode {
+      KeyCode::Escape | KeyCode::Return => SceneTransition::Pop,
+      _ => SceneTransition::None
+    }
+  }
+}
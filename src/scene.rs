@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+use ggez::{event, event::MouseButton, graphics, input::keyboard::{KeyCode, KeyMods}, Context, GameResult};
+
+use crate::assets::Assets;
+use crate::settings::Settings;
+
+// State shared by every scene for the lifetime of the game
+pub struct World {
+  pub assets: Rc<Assets>,
+  pub settings: Settings,
+}
+
+pub enum SceneTransition {
+  None,
+  Push(Box<dyn Scene>),
+  Pop,
+  Replace(Box<dyn Scene>),
+}
+
+pub trait Scene {
+  fn update(&mut self, ctx: &mut Context, world: &mut World) -> GameResult<SceneTransition>;
+
+  fn draw(&mut self, ctx: &mut Context, world: &World) -> GameResult<()>;
+
+  fn key_down_event(
+    &mut self,
+    _ctx: &mut Context,
+    _world: &mut World,
+    _keycode: KeyCode,
+    _keymods: KeyMods,
+    _repeat: bool
+  ) -> SceneTransition {
+    SceneTransition::None
+  }
+
+  fn key_up_event(&mut self, _ctx: &mut Context, _world: &mut World, _keycode: KeyCode, _keymods: KeyMods) {}
+
+  fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, _x: f32, _y: f32) {}
+
+  fn mouse_motion_event(&mut self, _ctx: &mut Context, _x: f32, _y: f32, _xrel: f32, _yrel: f32) {}
+
+  fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, _y: f32) {}
+}
+
+// Only the scene on top of the stack gets updates, draws and input
+pub struct SceneStack {
+  world: World,
+  scenes: Vec<Box<dyn Scene>>,
+}
+
+impl SceneStack {
+  pub fn new(world: World, initial_scene: Box<dyn Scene>) -> SceneStack {
+    SceneStack { world: world, scenes: vec![initial_scene] }
+  }
+
+  fn apply(&mut self, ctx: &mut Context, transition: SceneTransition) {
+    match transition {
+      SceneTransition::None => {},
+      SceneTransition::Push(scene) => self.scenes.push(scene),
+      SceneTransition::Pop => { self.scenes.pop(); },
+      SceneTransition::Replace(scene) => {
+        self.scenes.pop();
+        self.scenes.push(scene);
+      },
+    }
+
+    if self.scenes.is_empty() {
+      event::quit(ctx);
+    }
+  }
+}
+
+impl event::EventHandler for SceneStack {
+  fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+    let transition = match self.scenes.last_mut() {
+      Some(scene) => scene.update(ctx, &mut self.world)?,
+      None => SceneTransition::None,
+    };
+    self.apply(ctx, transition);
+    Ok(())
+  }
+
+  fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+    graphics::clear(ctx, graphics::WHITE);
+    if let Some(scene) = self.scenes.last_mut() {
+      scene.draw(ctx, &self.world)?;
+    }
+    graphics::present(ctx)
+  }
+
+  fn key_down_event(
+    &mut self,
+    ctx: &mut Context,
+    keycode: KeyCode,
+    keymods: KeyMods,
+    repeat: bool
+  ) {
+    let transition = match self.scenes.last_mut() {
+      Some(scene) => scene.key_down_event(ctx, &mut self.world, keycode, keymods, repeat),
+      None => SceneTransition::None,
+    };
+    self.apply(ctx, transition);
+  }
+
+  fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
+    if let Some(scene) = self.scenes.last_mut() {
+      scene.key_up_event(ctx, &mut self.world, keycode, keymods);
+    }
+  }
+
+  fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+    if let Some(scene) = self.scenes.last_mut() {
+      scene.mouse_button_down_event(ctx, button, x, y);
+    }
+  }
+
+  fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, xrel: f32, yrel: f32) {
+    if let Some(scene) = self.scenes.last_mut() {
+      scene.mouse_motion_event(ctx, x, y, xrel, yrel);
+    }
+  }
+
+  fn mouse_wheel_event(&mut self, ctx: &mut Context, x: f32, y: f32) {
+    if let Some(scene) = self.scenes.last_mut() {
+      scene.mouse_wheel_event(ctx, x, y);
+    }
+  }
+}
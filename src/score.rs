@@ -0,0 +1,89 @@
+use crate::judgement::{Grade, Judgement};
+
+pub const GRADES: [Grade; 5] = [Grade::Perfect, Grade::Great, Grade::Good, Grade::Bad, Grade::Miss];
+
+#[derive(Clone, Debug, Default)]
+pub struct ScoreState {
+  pub combo: u32,
+  pub max_combo: u32,
+  grade_counts: [u32; 5],
+  // Running sums over every judgement that had an offset, for the mean and deviation
+  offset_count: u32,
+  offset_sum_ms: f64,
+  offset_sum_sq_ms: f64,
+}
+
+impl ScoreState {
+  pub fn new() -> ScoreState {
+    ScoreState::default()
+  }
+
+  pub fn record(&mut self, judgement: &Judgement) {
+    let grade_idx = GRADES.iter().position(|&g| g == judgement.grade).unwrap();
+    self.grade_counts[grade_idx] += 1;
+
+    match judgement.grade {
+      Grade::Bad | Grade::Miss => self.combo = 0,
+      _ => {
+        self.combo += 1;
+        self.max_combo = self.max_combo.max(self.combo);
+      }
+    }
+
+    if let Some(offset_ms) = judgement.offset_ms {
+      self.offset_count += 1;
+      self.offset_sum_ms += offset_ms as f64;
+      self.offset_sum_sq_ms += (offset_ms as f64) * (offset_ms as f64);
+    }
+  }
+
+  pub fn count(&self, grade: Grade) -> u32 {
+    let grade_idx = GRADES.iter().position(|&g| g == grade).unwrap();
+    self.grade_counts[grade_idx]
+  }
+
+  pub fn judged_notes(&self) -> u32 {
+    self.grade_counts.iter().sum()
+  }
+
+  // Percentage of the best possible result over the notes judged so far
+  pub fn accuracy(&self) -> f64 {
+    let judged = self.judged_notes();
+    if judged == 0 {
+      return 100.0;
+    }
+    let earned: f64 = GRADES.iter().map(|&g| g.weight() * (self.count(g) as f64)).sum();
+    earned * 100.0/(judged as f64)
+  }
+
+  // Positive means the player tends to press late
+  pub fn mean_offset_ms(&self) -> Option<f64> {
+    if self.offset_count == 0 {
+      return None;
+    }
+    Some(self.offset_sum_ms/(self.offset_count as f64))
+  }
+
+  pub fn offset_std_dev_ms(&self) -> Option<f64> {
+    let mean = self.mean_offset_ms()?;
+    let variance = self.offset_sum_sq_ms/(self.offset_count as f64) - mean * mean;
+    Some(variance.max(0.0).sqrt())
+  }
+
+  pub fn rank(&self) -> &'static str {
+    let accuracy = self.accuracy();
+    if self.judged_notes() > 0 && self.count(Grade::Perfect) == self.judged_notes() {
+      "SS"
+    } else if accuracy >= 95.0 {
+      "S"
+    } else if accuracy >= 90.0 {
+      "A"
+    } else if accuracy >= 80.0 {
+      "B"
+    } else if accuracy >= 70.0 {
+      "C"
+    } else {
+      "D"
+    }
+  }
+}
@@ -0,0 +1,43 @@
+use std::io::Write;
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::judgement::JudgementWindows;
+
+const SETTINGS_PATH: &str = "/settings.ron";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Settings {
+  // Average lag between the audio and the player's key presses, measured by calibration
+  #[serde(default)]
+  pub input_offset_ms: i32,
+  #[serde(default)]
+  pub judgement_windows: JudgementWindows,
+}
+
+impl Settings {
+  pub fn load(ctx: &mut Context) -> Settings {
+    if !filesystem::exists(ctx, SETTINGS_PATH) {
+      return Settings::default();
+    }
+    let loaded = filesystem::open(ctx, SETTINGS_PATH)
+      .map_err(|e| e.to_string())
+      .and_then(|file| ron::de::from_reader(file).map_err(|e| e.to_string()));
+    match loaded {
+      Ok(settings) => settings,
+      Err(e) => {
+        println!("Ignoring invalid settings: {}", e);
+        Settings::default()
+      }
+    }
+  }
+
+  pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+    let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+      .map_err(|e| GameError::ResourceLoadError(format!("Unable to serialize settings: {}", e)))?;
+    let mut file = filesystem::create(ctx, SETTINGS_PATH)?;
+    file.write_all(text.as_bytes())?;
+    Ok(())
+  }
+}
@@ -0,0 +1,62 @@
+use std::{io::Read, path};
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::Deserialize;
+
+use crate::chart::{ChordReduction, LaneMode, NoteSource};
+use crate::quantize::Quantization;
+
+fn default_lead_in_ms() -> u32 { 1000 }
+fn default_bpm() -> f64 { 120.0 }
+
+// Everything needed to play one song, loaded from a RON file under resources/music
+#[derive(Clone, Debug, Deserialize)]
+pub struct SongManifest {
+  pub title: String,
+  pub audio: String,
+  pub chart: String,
+  pub sources: Vec<NoteSource>,
+  #[serde(default)]
+  pub chords: ChordReduction,
+  #[serde(default)]
+  pub lanes: LaneMode,
+  // Snaps notes to a beat grid; best left off for charts that were entered on the grid already
+  #[serde(default)]
+  pub quantize: Option<Quantization>,
+  // How far into the audio the chart's first tick lands; negative if the chart starts first,
+  // in which case notes before the audio starts are dropped
+  #[serde(default)]
+  pub audio_offset_ms: i32,
+  #[serde(default = "default_lead_in_ms")]
+  pub lead_in_ms: u32,
+  // Only used for timecode-based charts without any tempo events
+  #[serde(default = "default_bpm")]
+  pub bpm: f64,
+  // Track holding the battle cues, if they aren't mixed in with the music's own text events
+  #[serde(default)]
+  pub control_track: Option<usize>,
+  pub encounter: String,
+}
+
+impl SongManifest {
+  pub fn load<P: AsRef<path::Path>>(ctx: &mut Context, path: P) -> GameResult<SongManifest> {
+    let file = filesystem::open(ctx, path.as_ref())?;
+    ron::de::from_reader(file).map_err(|e| {
+      GameError::ResourceLoadError(format!("Invalid song manifest {}: {}", path.as_ref().display(), e))
+    })
+  }
+
+  pub fn read_chart(&self, ctx: &mut Context) -> GameResult<Vec<u8>> {
+    read_resource(ctx, &self.chart)
+  }
+
+  pub fn read_audio(&self, ctx: &mut Context) -> GameResult<Vec<u8>> {
+    read_resource(ctx, &self.audio)
+  }
+}
+
+fn read_resource(ctx: &mut Context, path: &str) -> GameResult<Vec<u8>> {
+  let mut bytes = Vec::new();
+  filesystem::open(ctx, path)?.read_to_end(&mut bytes)?;
+  Ok(bytes)
+}
@@ -0,0 +1,180 @@
+use std::path;
+
+use ggez::{filesystem, graphics, input::keyboard::{KeyCode, KeyMods}, Context, GameError, GameResult};
+use midly::Smf;
+use nalgebra::Point2;
+
+use crate::battle::BattleScene;
+use crate::chart::get_pattern;
+use crate::difficulty::{Difficulty, DIFFICULTIES};
+use crate::quantize::QuantizeReport;
+use crate::scene::{Scene, SceneTransition, World};
+use crate::song::SongManifest;
+use crate::timing::get_timing;
+
+const SONGS_DIR: &str = "/music";
+
+struct SongEntry {
+  path: path::PathBuf,
+  manifest: SongManifest,
+  // How much quantizing moved the chart, for songs that quantize
+  quantize_report: Option<QuantizeReport>,
+}
+
+fn quantize_report(ctx: &mut Context, manifest: &SongManifest) -> GameResult<Option<QuantizeReport>> {
+  let quantization = match &manifest.quantize {
+    Some(quantization) => quantization,
+    None => return Ok(None),
+  };
+  let midi_bytes = manifest.read_chart(ctx)?;
+  let midi = Smf::parse(&midi_bytes)
+    .map_err(|e| GameError::ResourceLoadError(format!("Invalid chart {}: {}", manifest.chart, e)))?;
+  let timing = get_timing(&midi, manifest.bpm, manifest.audio_offset_ms);
+  // Quantizing happens before thinning, so the difficulty doesn't change the report
+  let (_, report) = get_pattern(&midi, &timing, &manifest.sources, &manifest.chords, Some(quantization), Difficulty::Hard);
+  Ok(report)
+}
+
+pub struct SongSelectScene {
+  songs: Vec<SongEntry>,
+  selected: usize,
+  difficulty: Difficulty,
+  // Why the last song picked couldn't be played
+  load_error: Option<String>,
+}
+
+impl SongSelectScene {
+  pub fn new(ctx: &mut Context) -> SongSelectScene {
+    let mut manifest_paths: Vec<path::PathBuf> = filesystem::read_dir(ctx, SONGS_DIR)
+      .unwrap()
+      .filter(|path| match path.extension() {
+        Some(ext) => ext == "ron",
+        None => false
+      })
+      .collect();
+    manifest_paths.sort();
+
+    let songs = manifest_paths
+      .into_iter()
+      .filter_map(|path| match SongManifest::load(ctx, &path).and_then(|m| Ok((quantize_report(ctx, &m)?, m))) {
+        Ok((quantize_report, manifest)) => Some(SongEntry { path: path, manifest: manifest, quantize_report: quantize_report }),
+        Err(e) => {
+          println!("Skipping song: {}", e);
+          None
+        }
+      })
+      .collect();
+
+    SongSelectScene { songs: songs, selected: 0, difficulty: Difficulty::Normal, load_error: None }
+  }
+}
+
+impl Scene for SongSelectScene {
+  fn update(&mut self, _ctx: &mut Context, _world: &mut World) -> GameResult<SceneTransition> {
+    Ok(SceneTransition::None)
+  }
+
+  fn draw(&mut self, ctx: &mut Context, world: &World) -> GameResult<()> {
+    graphics::draw(
+      ctx,
+      &graphics::Text::new(("Select a song", world.assets.font, 75.0)),
+      graphics::DrawParam::default().dest(Point2::new(100.0, 50.0)).color(graphics::BLACK)
+    )?;
+
+    graphics::draw(
+      ctx,
+      &graphics::Text::new((format!("< {} >", self.difficulty.label()), world.assets.font, 40.0)),
+      graphics::DrawParam::default().dest(Point2::new(100.0, 135.0)).color(graphics::BLACK)
+    )?;
+
+    if self.songs.is_empty() {
+      graphics::draw(
+        ctx,
+        &graphics::Text::new((format!("No songs found in {}", SONGS_DIR), world.assets.font, 40.0)),
+        graphics::DrawParam::default().dest(Point2::new(100.0, 200.0)).color(graphics::BLACK)
+      )?;
+    }
+
+    for (i, song) in self.songs.iter().enumerate() {
+      let y = 200.0 + (i as f32) * 60.0;
+      if i == self.selected {
+        graphics::draw(
+          ctx,
+          &world.assets.cursor,
+          graphics::DrawParam::default().dest(Point2::new(100.0, y))
+        )?;
+      }
+      graphics::draw(
+        ctx,
+        &graphics::Text::new((song.manifest.title.as_str(), world.assets.font, 40.0)),
+        graphics::DrawParam::default().dest(Point2::new(180.0, y)).color(graphics::BLACK)
+      )?;
+    }
+
+    let below_songs_y = 200.0 + (self.songs.len() as f32) * 60.0 + 20.0;
+    if let Some(report) = self.songs.get(self.selected).and_then(|song| song.quantize_report) {
+      graphics::draw(
+        ctx,
+        &graphics::Text::new((format!("Quantized: {}", report), world.assets.font, 24.0)),
+        graphics::DrawParam::default().dest(Point2::new(100.0, below_songs_y)).color(graphics::BLACK)
+      )?;
+    }
+
+    if let Some(error) = &self.load_error {
+      let y = below_songs_y + 40.0;
+      graphics::draw(
+        ctx,
+        &graphics::Text::new((format!("Can't play this song: {}", error), world.assets.font, 24.0)),
+        graphics::DrawParam::default().dest(Point2::new(100.0, y)).color(graphics::Color::from_rgb(200, 0, 0))
+      )?;
+    }
+
+    Ok(())
+  }
+
+  fn key_down_event(
+    &mut self,
+    ctx: &mut Context,
+    world: &mut World,
+    keycode: KeyCode,
+    _keymods: KeyMods,
+    repeat: bool
+  ) -> SceneTransition {
+    if repeat { return SceneTransition::None; }
+
+    match keycode {
+      KeyCode::Escape => return SceneTransition::Pop,
+      KeyCode::Up if self.selected > 0 => {
+        self.selected -= 1;
+        self.load_error = None;
+      },
+      KeyCode::Down if self.selected + 1 < self.songs.len() => {
+        self.selected += 1;
+        self.load_error = None;
+      },
+      KeyCode::Left | KeyCode::Right => {
+        let idx = DIFFICULTIES.iter().position(|&d| d == self.difficulty).unwrap();
+        let idx = match keycode {
+          KeyCode::Left => idx.saturating_sub(1),
+          _ => (idx + 1).min(DIFFICULTIES.len() - 1),
+        };
+        self.difficulty = DIFFICULTIES[idx];
+      },
+      KeyCode::Return if !self.songs.is_empty() => {
+        let path = self.songs[self.selected].path.to_str().unwrap().to_owned();
+        match BattleScene::new(ctx, world, &path, self.difficulty) {
+          Ok(battle) => {
+            self.load_error = None;
+            return SceneTransition::Push(Box::new(battle));
+          },
+          Err(e) => {
+            println!("Can't play song: {}", e);
+            self.load_error = Some(e.to_string());
+          }
+        }
+      },
+      _ => {}
+    }
+    SceneTransition::None
+  }
+}
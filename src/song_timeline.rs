@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use rodio::{Sample, Source};
+
+use crate::counting_source::{CountingSource, PlaybackClock};
+
+// Plays silence for a fixed number of frames, then the wrapped source
+pub struct PreRoll<I> {
+  input: I,
+  silent_samples_left: u64,
+}
+
+impl<I> Iterator for PreRoll<I>
+where
+  I: Source,
+  I::Item: Sample,
+{
+  type Item = I::Item;
+
+  #[inline]
+  fn next(&mut self) -> Option<I::Item> {
+    if self.silent_samples_left > 0 {
+      self.silent_samples_left -= 1;
+      return Some(I::Item::zero_value());
+    }
+    self.input.next()
+  }
+
+  #[inline]
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let (lower, upper) = self.input.size_hint();
+    let silent = self.silent_samples_left as usize;
+    (lower + silent, upper.map(|u| u + silent))
+  }
+}
+
+impl<I> Source for PreRoll<I>
+where
+  I: Source,
+  I::Item: Sample,
+{
+  #[inline]
+  fn current_frame_len(&self) -> Option<usize> {
+    if self.silent_samples_left > 0 {
+      Some(self.silent_samples_left as usize)
+    } else {
+      self.input.current_frame_len()
+    }
+  }
+
+  #[inline]
+  fn channels(&self) -> u16 {
+    self.input.channels()
+  }
+
+  #[inline]
+  fn sample_rate(&self) -> u32 {
+    self.input.sample_rate()
+  }
+
+  #[inline]
+  fn total_duration(&self) -> Option<Duration> {
+    None
+  }
+}
+
+// Song time is zero where the music starts, and negative during the pre-roll
+pub struct SongClock {
+  clock: PlaybackClock,
+  pre_roll_ms: f64,
+}
+
+impl SongClock {
+  pub fn song_time_ms(&mut self) -> f64 {
+    self.clock.now_ms() - self.pre_roll_ms
+  }
+}
+
+pub fn song_timeline<I>(music: I, pre_roll_ms: u32) -> (CountingSource<PreRoll<I>>, SongClock)
+where
+  I: Source,
+  I::Item: Sample,
+{
+  let sample_rate = music.sample_rate() as u64;
+  let pre_roll_frames = (pre_roll_ms as u64) * sample_rate/1000;
+  let pre_roll = PreRoll {
+    silent_samples_left: pre_roll_frames * (music.channels() as u64),
+    input: music,
+  };
+
+  let (source, clock) = CountingSource::new(pre_roll);
+  let song_clock = SongClock {
+    clock: clock,
+    // The pre-roll is a whole number of frames, so it can be slightly shorter than requested
+    pre_roll_ms: (pre_roll_frames as f64) * 1000.0/(sample_rate as f64),
+  };
+  (source, song_clock)
+}
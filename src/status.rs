@@ -0,0 +1,119 @@
+use std::mem;
+
+use ggez::graphics::Color;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum StatusKind {
+  // Loses HP on every beat
+  Poison { damage_per_beat: u32 },
+  // Can't act
+  Stun,
+  // Absorbs incoming damage until used up
+  Shield { absorb: u32 },
+  // Attacks strike an extra time
+  Haste,
+}
+
+impl StatusKind {
+  pub fn icon_label(&self) -> &'static str {
+    match self {
+      StatusKind::Poison { .. } => "Psn",
+      StatusKind::Stun => "Stn",
+      StatusKind::Shield { .. } => "Shd",
+      StatusKind::Haste => "Hst",
+    }
+  }
+
+  pub fn icon_color(&self) -> Color {
+    match self {
+      StatusKind::Poison { .. } => Color::from_rgb(120, 0, 160),
+      StatusKind::Stun => Color::from_rgb(230, 170, 0),
+      StatusKind::Shield { .. } => Color::from_rgb(0, 120, 220),
+      StatusKind::Haste => Color::from_rgb(0, 170, 80),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum StatusDuration {
+  Beats(u32),
+  Measures(u32),
+}
+
+impl StatusDuration {
+  pub fn is_zero(&self) -> bool {
+    match *self {
+      StatusDuration::Beats(n) | StatusDuration::Measures(n) => n == 0,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StatusEffect {
+  pub kind: StatusKind,
+  pub remaining: StatusDuration,
+}
+
+// The lasting effects on one hero or enemy; at most one of each kind
+#[derive(Clone, Debug, Default)]
+pub struct StatusEffects {
+  effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+  pub fn iter(&self) -> impl Iterator<Item = &StatusEffect> {
+    self.effects.iter()
+  }
+
+  // Replaces any effect of the same kind, so reapplying an effect refreshes it
+  pub fn add(&mut self, kind: StatusKind, duration: StatusDuration) {
+    self.effects.retain(|e| mem::discriminant(&e.kind) != mem::discriminant(&kind));
+    self.effects.push(StatusEffect { kind: kind, remaining: duration });
+  }
+
+  pub fn clear(&mut self) {
+    self.effects.clear();
+  }
+
+  // Counts down every effect by one beat, and by one measure if a measure just started
+  pub fn tick_beat(&mut self, measure_started: bool) {
+    for effect in self.effects.iter_mut() {
+      match effect.remaining {
+        StatusDuration::Beats(ref mut n) => *n = n.saturating_sub(1),
+        StatusDuration::Measures(ref mut n) if measure_started => *n = n.saturating_sub(1),
+        StatusDuration::Measures(_) => {},
+      }
+    }
+    self.effects.retain(|e| !e.remaining.is_zero());
+  }
+
+  pub fn is_stunned(&self) -> bool {
+    self.effects.iter().any(|e| matches!(e.kind, StatusKind::Stun))
+  }
+
+  pub fn is_hasted(&self) -> bool {
+    self.effects.iter().any(|e| matches!(e.kind, StatusKind::Haste))
+  }
+
+  pub fn poison_damage(&self) -> u32 {
+    self.effects
+      .iter()
+      .map(|e| match e.kind { StatusKind::Poison { damage_per_beat } => damage_per_beat, _ => 0 })
+      .sum()
+  }
+
+  // Soaks up as much of the damage as shields allow, returning what gets through
+  pub fn absorb_damage(&mut self, damage: u32) -> u32 {
+    let mut damage = damage;
+    for effect in self.effects.iter_mut() {
+      if let StatusKind::Shield { ref mut absorb } = effect.kind {
+        let absorbed = damage.min(*absorb);
+        *absorb -= absorbed;
+        damage -= absorbed;
+      }
+    }
+    self.effects.retain(|e| match e.kind { StatusKind::Shield { absorb } => absorb > 0, _ => true });
+    damage
+  }
+}
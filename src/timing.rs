@@ -0,0 +1,236 @@
+use midly::{Smf, EventKind, Fps, MetaMessage, Timing};
+
+use crate::midi_events::absolute_events;
+
+// Tempo assumed by the MIDI spec until the first tempo event (120 BPM)
+const DEFAULT_MICROSECONDS_PER_BEAT: u32 = 500_000;
+
+// Beats that land this close to a barline are counted as on it, so float error in tempo
+// conversions doesn't push them back into the measure before
+const BARLINE_EPSILON_BEATS: f64 = 0.0001;
+
+// What a MIDI tick measures: a fraction of a beat (metrical timing), or a fraction
+// of a second (SMPTE timecode timing)
+#[derive(Copy, Clone, Debug)]
+pub enum TickUnit {
+  Beats { ticks_per_beat: f64 },
+  Seconds { ticks_per_second: f64 },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TempoChange {
+  tick: u64,
+  ms: f64,
+  beat: f64,
+  ms_per_tick: f64,
+  ms_per_beat: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+  changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+  // Tempos are (tick, microseconds per beat) pairs, and must be sorted by tick
+  pub fn new(unit: TickUnit, initial_ms_per_beat: f64, tempos: &[(u64, u32)]) -> TempoMap {
+    let ms_per_tick = |ms_per_beat: f64| match unit {
+      TickUnit::Beats { ticks_per_beat } => ms_per_beat/ticks_per_beat,
+      TickUnit::Seconds { ticks_per_second } => 1000.0/ticks_per_second,
+    };
+
+    let mut changes = vec![TempoChange {
+      tick: 0,
+      ms: 0.0,
+      beat: 0.0,
+      ms_per_tick: ms_per_tick(initial_ms_per_beat),
+      ms_per_beat: initial_ms_per_beat,
+    }];
+    for &(tick, mspb) in tempos {
+      let prior = *changes.last().unwrap();
+      let ms = prior.ms + ((tick - prior.tick) as f64) * prior.ms_per_tick;
+      let ms_per_beat = (mspb as f64)/1000.0;
+      let change = TempoChange {
+        tick: tick,
+        ms: ms,
+        beat: prior.beat + (ms - prior.ms)/prior.ms_per_beat,
+        ms_per_tick: ms_per_tick(ms_per_beat),
+        ms_per_beat: ms_per_beat,
+      };
+      if change.tick == prior.tick {
+        // Later events at the same tick override earlier ones
+        *changes.last_mut().unwrap() = change;
+      } else {
+        changes.push(change);
+      }
+    }
+
+    TempoMap { changes: changes }
+  }
+
+  fn change_at_ms(&self, ms: f64) -> &TempoChange {
+    self.changes.iter().rev().find(|c| c.ms <= ms).unwrap_or(&self.changes[0])
+  }
+
+  pub fn tick_to_ms(&self, tick: f64) -> f64 {
+    let change = self.changes.iter().rev().find(|c| (c.tick as f64) <= tick).unwrap_or(&self.changes[0]);
+    change.ms + (tick - (change.tick as f64)) * change.ms_per_tick
+  }
+
+  pub fn beat_to_ms(&self, beat: f64) -> f64 {
+    let change = self.changes.iter().rev().find(|c| c.beat <= beat).unwrap_or(&self.changes[0]);
+    change.ms + (beat - change.beat) * change.ms_per_beat
+  }
+
+  pub fn ms_to_beat(&self, ms: f64) -> f64 {
+    let change = self.change_at_ms(ms);
+    change.beat + (ms - change.ms)/change.ms_per_beat
+  }
+
+  pub fn tick_to_beat(&self, tick: f64) -> f64 {
+    self.ms_to_beat(self.tick_to_ms(tick))
+  }
+
+  pub fn ms_per_beat_at_ms(&self, ms: f64) -> f64 {
+    self.change_at_ms(ms).ms_per_beat
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct MeterChange {
+  beat: f64,
+  measure_idx: usize,
+  beats_per_measure: f64,
+}
+
+// Maps beats (always quarter notes, like MIDI tempo) onto measures
+#[derive(Clone, Debug)]
+pub struct MeasureMap {
+  changes: Vec<MeterChange>,
+}
+
+impl MeasureMap {
+  // Signatures are (beat, numerator, denominator as a power of two) triples, sorted by beat
+  pub fn new(signatures: &[(f64, u8, u8)]) -> MeasureMap {
+    // MIDI files with no time signature are 4/4 by definition
+    let mut changes = vec![MeterChange { beat: 0.0, measure_idx: 0, beats_per_measure: 4.0 }];
+    for &(beat, numerator, denominator_pow) in signatures {
+      let prior = *changes.last().unwrap();
+      // A meter change partway through a measure cuts that measure short
+      let prior_measures = ((beat - prior.beat)/prior.beats_per_measure - BARLINE_EPSILON_BEATS).ceil().max(0.0) as usize;
+      let change = MeterChange {
+        beat: beat,
+        measure_idx: prior.measure_idx + prior_measures,
+        beats_per_measure: (numerator as f64) * 4.0/((1u32 << denominator_pow) as f64),
+      };
+      if prior_measures == 0 {
+        *changes.last_mut().unwrap() = MeterChange { beat: prior.beat, ..change };
+      } else {
+        changes.push(change);
+      }
+    }
+
+    MeasureMap { changes: changes }
+  }
+
+  pub fn measure_start_beat(&self, measure_idx: usize) -> f64 {
+    let change = self.changes.iter().rev().find(|c| c.measure_idx <= measure_idx).unwrap();
+    change.beat + ((measure_idx - change.measure_idx) as f64) * change.beats_per_measure
+  }
+
+  pub fn measure_idx_at_beat(&self, beat: f64) -> usize {
+    if beat <= 0.0 {
+      return 0;
+    }
+    let change = self.changes.iter().rev().find(|c| c.beat <= beat + BARLINE_EPSILON_BEATS).unwrap();
+    change.measure_idx + ((beat - change.beat)/change.beats_per_measure + BARLINE_EPSILON_BEATS) as usize
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct MidiTiming {
+  pub tempo_map: TempoMap,
+  pub measure_map: MeasureMap,
+  // Where the start of the MIDI falls in the audio; all ms values below are in audio time
+  pub audio_offset_ms: f64,
+}
+
+impl MidiTiming {
+  pub fn tick_to_ms(&self, tick: u64) -> f64 {
+    self.tempo_map.tick_to_ms(tick as f64) + self.audio_offset_ms
+  }
+
+  pub fn ms_per_beat_at_ms(&self, ms: f64) -> f64 {
+    self.tempo_map.ms_per_beat_at_ms(ms - self.audio_offset_ms)
+  }
+
+  pub fn beat_to_ms(&self, beat: f64) -> f64 {
+    self.tempo_map.beat_to_ms(beat) + self.audio_offset_ms
+  }
+
+  pub fn beat_at_ms(&self, ms: f64) -> f64 {
+    self.tempo_map.ms_to_beat(ms - self.audio_offset_ms)
+  }
+
+  pub fn measure_start_ms(&self, measure_idx: usize) -> f64 {
+    self.tempo_map.beat_to_ms(self.measure_map.measure_start_beat(measure_idx)) + self.audio_offset_ms
+  }
+
+  pub fn measure_idx_at_ms(&self, ms: f64) -> usize {
+    self.measure_map.measure_idx_at_beat(self.beat_at_ms(ms))
+  }
+
+  // Goes straight from tick to beat, skipping the audio offset round trip
+  pub fn measure_idx_at_tick(&self, tick: u64) -> usize {
+    self.measure_map.measure_idx_at_beat(self.tempo_map.tick_to_beat(tick as f64))
+  }
+}
+
+// Timecode MIDI has no inherent beat, so fallback_bpm sets one if it has no tempo events
+pub fn get_timing(midi: &Smf, fallback_bpm: f64, audio_offset_ms: i32) -> MidiTiming {
+  let (unit, initial_ms_per_beat) = match midi.header.timing {
+    Timing::Metrical(n) => (
+      TickUnit::Beats { ticks_per_beat: n.as_int().into() },
+      (DEFAULT_MICROSECONDS_PER_BEAT as f64)/1000.0
+    ),
+    Timing::Timecode(fps, subframes) => {
+      let fps = match fps {
+        Fps::Fps24 => 24.0,
+        Fps::Fps25 => 25.0,
+        Fps::Fps29 => 30000.0/1001.0, // Drop-frame
+        Fps::Fps30 => 30.0,
+      };
+      (
+        TickUnit::Seconds { ticks_per_second: fps * (subframes as f64) },
+        60_000.0/fallback_bpm
+      )
+    },
+  };
+
+  let mut tempos: Vec<(u64, u32)> = Vec::new();
+  let mut signatures: Vec<(u64, u8, u8)> = Vec::new();
+  // Format 1 files keep these in track 0, but format 0 and 2 files can have them anywhere
+  for event in absolute_events(midi) {
+    match event.kind {
+      EventKind::Meta(MetaMessage::Tempo(mspb)) => {
+        tempos.push((event.tick, mspb.as_int()));
+      },
+      EventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow, _, _)) => {
+        signatures.push((event.tick, numerator, denominator_pow));
+      },
+      _ => {}
+    }
+  }
+
+  let tempo_map = TempoMap::new(unit, initial_ms_per_beat, &tempos);
+  let signatures: Vec<(f64, u8, u8)> = signatures
+    .into_iter()
+    .map(|(tick, numerator, denominator_pow)| (tempo_map.tick_to_beat(tick as f64), numerator, denominator_pow))
+    .collect();
+
+  MidiTiming {
+    measure_map: MeasureMap::new(&signatures),
+    tempo_map: tempo_map,
+    audio_offset_ms: audio_offset_ms as f64,
+  }
+}
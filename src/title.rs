@@ -0,0 +1,58 @@
+use ggez::{graphics, input::keyboard::{KeyCode, KeyMods}, Context, GameResult};
+use nalgebra::Point2;
+
+use crate::calibration::CalibrationScene;
+use crate::scene::{Scene, SceneTransition, World};
+use crate::song_select::SongSelectScene;
+
+pub struct TitleScene {}
+
+impl TitleScene {
+  pub fn new() -> TitleScene {
+    TitleScene {}
+  }
+}
+
+impl Scene for TitleScene {
+  fn update(&mut self, _ctx: &mut Context, _world: &mut World) -> GameResult<SceneTransition> {
+    Ok(SceneTransition::None)
+  }
+
+  fn draw(&mut self, ctx: &mut Context, world: &World) -> GameResult<()> {
+    let window = graphics::screen_coordinates(ctx);
+
+    let title = graphics::Text::new(("Upbeat", world.assets.font, 150.0));
+    let x = (window.w - title.width(ctx) as f32)/2.0;
+    graphics::draw(
+      ctx,
+      &title,
+      graphics::DrawParam::default().dest(Point2::new(x, 150.0)).color(graphics::BLACK)
+    )?;
+
+    let prompt = graphics::Text::new(("Press enter to play, or C to calibrate", world.assets.font, 50.0));
+    let x = (window.w - prompt.width(ctx) as f32)/2.0;
+    graphics::draw(
+      ctx,
+      &prompt,
+      graphics::DrawParam::default().dest(Point2::new(x, 450.0)).color(graphics::BLACK)
+    )
+  }
+
+  fn key_down_event(
+    &mut self,
+    ctx: &mut Context,
+    _world: &mut World,
+    keycode: KeyCode,
+    _keymods: KeyMods,
+    repeat: bool
+  ) -> SceneTransition {
+    if repeat { return SceneTransition::None; }
+
+    match keycode {
+      KeyCode::Escape => SceneTransition::Pop,
+      KeyCode::Return => SceneTransition::Push(Box::new(SongSelectScene::new(ctx))),
+      KeyCode::C => SceneTransition::Push(Box::new(CalibrationScene::new())),
+      _ => SceneTransition::None
+    }
+  }
+}